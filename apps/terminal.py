@@ -6,26 +6,34 @@ line = ""
 buffer = []
 history = []
 history_item = None
+font_size = 6.0
+
+def line_height(text):
+    # Long lines wrap, empty lines still take a line
+    return max(watch.measure_text(text, font_size).y, font_size)
 
 def on_run_output(output):
     # print(output)
     buffer.extend(output.split('\n'))
+    if watch.run_failed():
+        watch.beep(220, 0.15)
 
 def frame(frame):
     global line
     global buffer
     global history
     global history_item
-    font_size = 6.0
 
-    while len(buffer) > (128.0 - 16.0) / font_size:
+    heights = [line_height(bline) for bline in buffer]
+    while buffer and 16.0 + sum(heights) + line_height("> " + line) > 128.0:
         buffer.pop(0)
+        heights.pop(0)
 
     frame.draw_image(Vector2(0, 0), ui.icons['cross'])
     cursor = Vector2(0, 16)
-    for bline in buffer:
+    for bline, height in zip(buffer, heights):
         frame.draw_text(cursor, bline, font_size, 0xffffff)
-        cursor = Vector2(0.0, cursor.y + font_size)
+        cursor = Vector2(0.0, cursor.y + height)
     frame.draw_text(cursor, "> " + line, font_size, 0xffffff)
 
     line += frame.typed_text()
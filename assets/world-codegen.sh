@@ -3,3 +3,6 @@ SCRIPT_DIR="$( cd "$( dirname "${BASH_SOURCE[0]}" )" &> /dev/null && pwd )"
 ldtk-codegen "$SCRIPT_DIR/../src/world.ldtk" \
 		--vector 'speedy2d::dimen::Vector2<T>' \
 		--color 'speedy2d::color::Color' \
+
+# The runtime loader (src/ldtk.rs) builds Solid layers too, so it needs their auto tiles
+sed -i 's/^    auto_tiles: Vec<Vec<Tile>>,$/    pub auto_tiles: Vec<Vec<Tile>>,/' "$SCRIPT_DIR/../src/world.rs"
@@ -1,6 +1,14 @@
 use speedy2d::image::ImageHandle;
 use speedy2d::Graphics2D;
 
+/// Player spritesheet, also used to get the player size without a graphics context
+pub const PLAYER_IMAGE: &[u8] = include_bytes!("../assets/player/image.png");
+
+/// Font of the game, also used to lay out text of apps without a graphics context
+pub static FONT: std::sync::LazyLock<speedy2d::font::Font> = std::sync::LazyLock::new(|| {
+    speedy2d::font::Font::new(include_bytes!("../assets/712_serif.ttf")).unwrap()
+});
+
 pub struct Assets {
     pub font: speedy2d::font::Font,
     pub tileset: ImageHandle,
@@ -24,7 +32,7 @@ pub struct WatchAssets {
 impl Assets {
     pub fn load(graphics: &mut Graphics2D) -> Self {
         Self {
-            font: speedy2d::font::Font::new(include_bytes!("../assets/712_serif.ttf")).unwrap(),
+            font: FONT.clone(),
             tileset: load_image(graphics, include_bytes!("../assets/tileset.png")),
             particles: load_image(graphics, include_bytes!("../assets/particles.png")),
 
@@ -33,7 +41,7 @@ impl Assets {
                 icons: load_image(graphics, include_bytes!("../assets/watch/icons.png")),
             },
             player: PlayerAssets {
-                image: load_image(graphics, include_bytes!("../assets/player/image.png")),
+                image: load_image(graphics, PLAYER_IMAGE),
                 image_flip: load_image(graphics, include_bytes!("../assets/player/image_flip.png")),
                 image_nowatch: load_image(graphics, include_bytes!("../assets/player/image_nowatch.png")),
             },
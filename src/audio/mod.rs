@@ -0,0 +1,499 @@
+//! Software audio mixer. Plays [`Sound`]s on volume [`Bus`]es and writes the mix to an [`Output`]
+use super::*;
+use std::sync::Arc;
+
+pub mod output;
+pub mod wav;
+
+pub use output::Output;
+
+/// Sample rate of the mix, sounds with other rates are resampled
+pub const SAMPLE_RATE: u32 = 44100;
+
+#[derive(Debug)]
+pub enum AudioError {
+    Io(std::io::Error),
+    Invalid(String),
+    Device(String),
+}
+
+impl std::fmt::Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "audio IO failed: {}", err),
+            Self::Invalid(message) => write!(f, "invalid sound: {}", message),
+            Self::Device(message) => write!(f, "audio device failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+impl From<std::io::Error> for AudioError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+// * Sounds
+/// Sample data in -1..1, channels interleaved
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sound {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<f32>,
+}
+
+impl Sound {
+    pub fn from_wav(bytes: &[u8]) -> Result<Self, AudioError> {
+        wav::decode(bytes)
+    }
+
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frames() as f32 / self.sample_rate as f32
+    }
+
+    /// Stereo frame at a fractional position, interpolated linearly
+    fn frame(&self, position: f64) -> (f32, f32) {
+        let index = position as usize;
+        let t = (position - index as f64) as f32;
+        let sample = |index: usize, channel: usize| {
+            self.samples
+                .get(index * self.channels as usize + channel)
+                .copied()
+                .unwrap_or(0.0)
+        };
+        let channel =
+            |channel: usize| sample(index, channel) * (1.0 - t) + sample(index + 1, channel) * t;
+        if self.channels == 1 {
+            let mono = channel(0);
+            (mono, mono)
+        } else {
+            (channel(0), channel(1))
+        }
+    }
+
+    /// Play tones one after another
+    pub fn sequence(tones: &[Tone]) -> Self {
+        Self {
+            sample_rate: SAMPLE_RATE,
+            channels: 1,
+            samples: tones
+                .iter()
+                .flat_map(|tone| tone.render().samples)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Triangle,
+    Sawtooth,
+    Noise,
+}
+
+/// A synthesized note. Frequency slides from `frequency` to `end_frequency`, volume fades out linearly
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    pub frequency: f32,
+    pub end_frequency: f32,
+    pub duration: f32,
+    pub volume: f32,
+}
+
+impl Tone {
+    pub fn new(waveform: Waveform, frequency: f32, duration: f32) -> Self {
+        Self {
+            waveform,
+            frequency,
+            end_frequency: frequency,
+            duration,
+            volume: 1.0,
+        }
+    }
+
+    pub fn slide(mut self, end_frequency: f32) -> Self {
+        self.end_frequency = end_frequency;
+        self
+    }
+
+    pub fn volume(mut self, volume: f32) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    pub fn render(&self) -> Sound {
+        let frames = (self.duration.max(0.0) * SAMPLE_RATE as f32) as usize;
+        // Short attack, so notes don't click
+        let attack = (0.005 * SAMPLE_RATE as f32) as usize;
+        let mut phase = 0.0_f32;
+        // Noise has to be the same every time, so sounds are reproducible
+        let mut noise = 0x2545F491_u32;
+        let mut samples = Vec::with_capacity(frames);
+        for index in 0..frames {
+            let t = index as f32 / frames as f32;
+            let frequency = self.frequency + (self.end_frequency - self.frequency) * t;
+            phase = (phase + frequency / SAMPLE_RATE as f32).fract();
+            let value = match self.waveform {
+                Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+                Waveform::Square => {
+                    if phase < 0.5 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                }
+                Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+                Waveform::Sawtooth => 2.0 * phase - 1.0,
+                Waveform::Noise => {
+                    noise ^= noise << 13;
+                    noise ^= noise >> 17;
+                    noise ^= noise << 5;
+                    noise as f32 / u32::MAX as f32 * 2.0 - 1.0
+                }
+            };
+            let envelope = (index as f32 / attack as f32).min(1.0) * (1.0 - t);
+            samples.push(value * envelope * self.volume);
+        }
+        Sound {
+            sample_rate: SAMPLE_RATE,
+            channels: 1,
+            samples,
+        }
+    }
+}
+
+// * Mixer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Bus {
+    Music,
+    Effects,
+    /// Sounds played by watch apps
+    Watch,
+}
+
+impl Bus {
+    pub const ALL: [Self; 3] = [Self::Music, Self::Effects, Self::Watch];
+}
+
+/// Sounds of game events
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Effect {
+    Jump,
+    Land,
+    Kick,
+    Slide,
+    DoorLock,
+    DoorUnlock,
+    LevelEnd,
+    Dialogue,
+}
+
+impl Effect {
+    pub const ALL: [Self; 8] = [
+        Self::Jump,
+        Self::Land,
+        Self::Kick,
+        Self::Slide,
+        Self::DoorLock,
+        Self::DoorUnlock,
+        Self::LevelEnd,
+        Self::Dialogue,
+    ];
+
+    pub fn sound(self) -> Sound {
+        use Waveform::*;
+        match self {
+            Self::Jump => {
+                Sound::sequence(&[Tone::new(Square, 300.0, 0.12).slide(600.0).volume(0.2)])
+            }
+            Self::Land => Sound::sequence(&[Tone::new(Noise, 0.0, 0.08).volume(0.3)]),
+            Self::Kick => {
+                Sound::sequence(&[Tone::new(Square, 400.0, 0.1).slide(900.0).volume(0.2)])
+            }
+            Self::Slide => Sound::sequence(&[Tone::new(Noise, 0.0, 0.3).volume(0.15)]),
+            Self::DoorLock => Sound::sequence(&[
+                Tone::new(Square, 440.0, 0.06).volume(0.2),
+                Tone::new(Square, 220.0, 0.1).volume(0.2),
+            ]),
+            Self::DoorUnlock => Sound::sequence(&[
+                Tone::new(Square, 220.0, 0.06).volume(0.2),
+                Tone::new(Square, 440.0, 0.1).volume(0.2),
+            ]),
+            Self::LevelEnd => Sound::sequence(&[
+                Tone::new(Triangle, 523.25, 0.1).volume(0.4),
+                Tone::new(Triangle, 659.25, 0.1).volume(0.4),
+                Tone::new(Triangle, 783.99, 0.1).volume(0.4),
+                Tone::new(Triangle, 1046.5, 0.3).volume(0.4),
+            ]),
+            Self::Dialogue => Sound::sequence(&[Tone::new(Triangle, 880.0, 0.04).volume(0.2)]),
+        }
+    }
+}
+
+/// Background music of the game, a calm arpeggio that loops every 4 seconds
+pub fn music() -> Sound {
+    const NOTES: [f32; 16] = [
+        261.63, 329.63, 392.0, 329.63, // C
+        220.0, 261.63, 329.63, 261.63, // Am
+        174.61, 220.0, 261.63, 220.0, // F
+        196.0, 246.94, 293.66, 246.94, // G
+    ];
+    let tones: Vec<_> = NOTES
+        .iter()
+        .map(|frequency| Tone::new(Waveform::Triangle, *frequency, 0.25).volume(0.3))
+        .collect();
+    Sound::sequence(&tones)
+}
+
+/// Handle of a playing sound
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VoiceId(u64);
+
+struct Voice {
+    id: VoiceId,
+    sound: Arc<Sound>,
+    bus: Bus,
+    volume: f32,
+    looped: bool,
+    /// Position in frames of the sound
+    position: f64,
+}
+
+pub struct Mixer {
+    voices: Vec<Voice>,
+    next_id: u64,
+    pub master: f32,
+    volumes: [f32; Bus::ALL.len()],
+    effects: Vec<(Effect, Arc<Sound>)>,
+    /// Fraction of a frame left over from the last mix
+    pending: f64,
+    buffer: Vec<f32>,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self {
+            voices: Vec::new(),
+            next_id: 0,
+            master: 1.0,
+            volumes: [1.0; Bus::ALL.len()],
+            effects: Effect::ALL
+                .iter()
+                .map(|effect| (*effect, Arc::new(effect.sound())))
+                .collect(),
+            pending: 0.0,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl Mixer {
+    pub fn play(&mut self, sound: Arc<Sound>, bus: Bus, volume: f32) -> VoiceId {
+        self.start(sound, bus, volume, false)
+    }
+
+    pub fn play_looped(&mut self, sound: Arc<Sound>, bus: Bus, volume: f32) -> VoiceId {
+        self.start(sound, bus, volume, true)
+    }
+
+    /// Loop `sound` on the music bus, replacing the music that was playing
+    pub fn play_music(&mut self, sound: Arc<Sound>, volume: f32) -> VoiceId {
+        self.voices.retain(|voice| voice.bus != Bus::Music);
+        self.play_looped(sound, Bus::Music, volume)
+    }
+
+    pub fn play_effect(&mut self, effect: Effect) {
+        if let Some((_, sound)) = self.effects.iter().find(|(other, _)| *other == effect) {
+            self.play(sound.clone(), Bus::Effects, 1.0);
+        }
+    }
+
+    fn start(&mut self, sound: Arc<Sound>, bus: Bus, volume: f32, looped: bool) -> VoiceId {
+        let id = VoiceId(self.next_id);
+        self.next_id += 1;
+        if sound.frames() > 0 {
+            self.voices.push(Voice {
+                id,
+                sound,
+                bus,
+                volume,
+                looped,
+                position: 0.0,
+            });
+        }
+        id
+    }
+
+    pub fn stop(&mut self, id: VoiceId) {
+        self.voices.retain(|voice| voice.id != id);
+    }
+
+    pub fn is_playing(&self, id: VoiceId) -> bool {
+        self.voices.iter().any(|voice| voice.id == id)
+    }
+
+    pub fn volume(&self, bus: Bus) -> f32 {
+        self.volumes[bus as usize]
+    }
+
+    pub fn set_volume(&mut self, bus: Bus, volume: f32) {
+        self.volumes[bus as usize] = volume.max(0.0);
+    }
+
+    /// Mix `delta_time` seconds of audio into `output`
+    pub fn mix(&mut self, delta_time: f32, output: &mut dyn Output) {
+        self.pending += delta_time as f64 * SAMPLE_RATE as f64;
+        let frames = self.pending as usize;
+        self.pending -= frames as f64;
+        if frames > 0 {
+            self.render(frames);
+            output.write(&self.buffer);
+        }
+    }
+
+    /// Render `frames` stereo frames into the buffer
+    fn render(&mut self, frames: usize) {
+        self.buffer.clear();
+        self.buffer.resize(frames * 2, 0.0);
+        for voice in &mut self.voices {
+            let step = voice.sound.sample_rate as f64 / SAMPLE_RATE as f64;
+            let length = voice.sound.frames() as f64;
+            let volume = voice.volume * self.volumes[voice.bus as usize] * self.master;
+            for frame in self.buffer.chunks_exact_mut(2) {
+                if voice.position >= length {
+                    if !voice.looped {
+                        break;
+                    }
+                    voice.position -= length;
+                }
+                let (left, right) = voice.sound.frame(voice.position);
+                frame[0] += left * volume;
+                frame[1] += right * volume;
+                voice.position += step;
+            }
+        }
+        self.voices
+            .retain(|voice| voice.looped || voice.position < voice.sound.frames() as f64);
+        for sample in &mut self.buffer {
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::output::NullOutput;
+    use super::*;
+
+    /// Keeps everything the mixer writes
+    #[derive(Default)]
+    struct Capture(Vec<f32>);
+
+    impl Output for Capture {
+        fn write(&mut self, samples: &[f32]) {
+            self.0.extend_from_slice(samples);
+        }
+    }
+
+    /// Mono sound at the mix rate holding `value` for `frames` frames
+    fn constant(value: f32, frames: usize) -> Arc<Sound> {
+        Arc::new(Sound {
+            sample_rate: SAMPLE_RATE,
+            channels: 1,
+            samples: vec![value; frames],
+        })
+    }
+
+    #[test]
+    fn mixes_voices_to_stereo() {
+        let mut mixer = Mixer::default();
+        mixer.play(constant(0.25, 22050), Bus::Effects, 1.0);
+        mixer.play(constant(0.25, 22050), Bus::Watch, 1.0);
+        let mut output = Capture::default();
+        mixer.mix(0.5, &mut output);
+        assert_eq!(output.0.len(), 22050 * 2);
+        assert!(output.0.iter().all(|sample| *sample == 0.5));
+    }
+
+    #[test]
+    fn clamps_the_mix() {
+        let mut mixer = Mixer::default();
+        mixer.play(constant(0.75, 22050), Bus::Effects, 1.0);
+        mixer.play(constant(0.75, 22050), Bus::Effects, 1.0);
+        mixer.play(constant(-0.75, 22050), Bus::Music, 0.0);
+        let mut output = Capture::default();
+        mixer.mix(0.5, &mut output);
+        assert!(output.0.iter().all(|sample| *sample == 1.0));
+    }
+
+    #[test]
+    fn applies_bus_and_voice_volumes() {
+        let mut mixer = Mixer::default();
+        mixer.set_volume(Bus::Music, 0.5);
+        mixer.play(constant(0.5, 22050), Bus::Music, 0.5);
+        let mut output = Capture::default();
+        mixer.mix(0.5, &mut output);
+        assert!(output.0.iter().all(|sample| *sample == 0.125));
+
+        mixer.set_volume(Bus::Music, -1.0);
+        assert_eq!(mixer.volume(Bus::Music), 0.0);
+    }
+
+    #[test]
+    fn pads_with_silence_after_the_end() {
+        let mut mixer = Mixer::default();
+        mixer.play(constant(0.5, 100), Bus::Effects, 1.0);
+        let mut output = Capture::default();
+        mixer.mix(0.5, &mut output);
+        assert!(output.0[..200].iter().all(|sample| *sample == 0.5));
+        assert!(output.0[200..].iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn renders_through_the_null_output() {
+        let mut mixer = Mixer::default();
+        let once = mixer.play(constant(0.5, 22050), Bus::Effects, 1.0);
+        let looped = mixer.play_looped(constant(0.5, 22050), Bus::Music, 1.0);
+        mixer.mix(0.25, &mut NullOutput);
+        assert!(mixer.is_playing(once));
+        mixer.mix(0.5, &mut NullOutput);
+        assert!(!mixer.is_playing(once));
+        assert!(mixer.is_playing(looped));
+
+        mixer.stop(looped);
+        assert!(!mixer.is_playing(looped));
+    }
+
+    #[test]
+    fn music_replaces_music() {
+        let mut mixer = Mixer::default();
+        let first = mixer.play_music(Arc::new(music()), 1.0);
+        let effect = mixer.play(constant(0.5, 22050), Bus::Effects, 1.0);
+        let second = mixer.play_music(Arc::new(music()), 1.0);
+        assert!(!mixer.is_playing(first));
+        assert!(mixer.is_playing(effect));
+        mixer.mix(5.0, &mut NullOutput);
+        assert!(mixer.is_playing(second));
+        assert!((music().duration() - 4.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn keeps_fractions_of_frames() {
+        let mut mixer = Mixer::default();
+        let mut output = Capture::default();
+        // 1/1024 seconds is about 43.07 frames
+        for _ in 0..1024 {
+            mixer.mix(1.0 / 1024.0, &mut output);
+        }
+        assert_eq!(output.0.len(), SAMPLE_RATE as usize * 2);
+    }
+}
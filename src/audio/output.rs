@@ -0,0 +1,140 @@
+//! Where mixed audio goes
+use super::*;
+
+/// Receives the mix as interleaved stereo samples at [`SAMPLE_RATE`]
+pub trait Output {
+    fn write(&mut self, samples: &[f32]);
+}
+
+/// Discards audio, for running without sound
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullOutput;
+
+impl Output for NullOutput {
+    fn write(&mut self, _samples: &[f32]) {}
+}
+
+/// Writes audio to a 16 bit WAV file. The header is updated on [`WavOutput::finish`] or drop
+#[cfg(not(target_arch = "wasm32"))]
+pub struct WavOutput {
+    file: std::io::BufWriter<std::fs::File>,
+    frames: u32,
+    error: Option<std::io::Error>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl WavOutput {
+    pub fn create(path: impl AsRef<std::path::Path>) -> Result<Self, AudioError> {
+        use std::io::Write;
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        file.write_all(&wav::header(2, SAMPLE_RATE, 0))?;
+        Ok(Self {
+            file,
+            frames: 0,
+            error: None,
+        })
+    }
+
+    /// Write the final header. Reports errors that happened while writing
+    pub fn finish(&mut self) -> Result<(), AudioError> {
+        use std::io::{Seek, Write};
+        if let Some(err) = self.error.take() {
+            return Err(err.into());
+        }
+        let position = self.file.stream_position()?;
+        self.file.seek(std::io::SeekFrom::Start(0))?;
+        self.file
+            .write_all(&wav::header(2, SAMPLE_RATE, self.frames))?;
+        self.file.seek(std::io::SeekFrom::Start(position))?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Output for WavOutput {
+    fn write(&mut self, samples: &[f32]) {
+        use std::io::Write;
+        if self.error.is_some() {
+            return;
+        }
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| ((sample * i16::MAX as f32) as i16).to_le_bytes())
+            .collect();
+        match self.file.write_all(&bytes) {
+            Ok(()) => self.frames += (samples.len() / 2) as u32,
+            Err(err) => self.error = Some(err),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for WavOutput {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            log_error(&err.to_string());
+        }
+    }
+}
+
+/// Plays audio on the default sound card, or with Web Audio on web
+#[cfg(feature = "audio-device")]
+pub struct DeviceOutput {
+    buffer: std::sync::Arc<std::sync::Mutex<std::collections::VecDeque<f32>>>,
+    _stream: cpal::Stream,
+}
+
+#[cfg(feature = "audio-device")]
+impl DeviceOutput {
+    /// Audio buffered above this is dropped, so a slow device doesn't build up latency
+    const MAX_BUFFERED: usize = SAMPLE_RATE as usize / 5 * 2;
+
+    pub fn new() -> Result<Self, AudioError> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| AudioError::Device("no output device".to_owned()))?;
+        let config = cpal::StreamConfig {
+            channels: 2,
+            sample_rate: cpal::SampleRate(SAMPLE_RATE),
+            buffer_size: cpal::BufferSize::Default,
+        };
+
+        let buffer = std::sync::Arc::new(std::sync::Mutex::new(std::collections::VecDeque::new()));
+        let stream = {
+            let buffer = buffer.clone();
+            device.build_output_stream(
+                &config,
+                move |data: &mut [f32], _| {
+                    let mut buffer = buffer.lock().unwrap();
+                    for sample in data {
+                        *sample = buffer.pop_front().unwrap_or(0.0);
+                    }
+                },
+                |err| log_error(&format!("audio stream failed: {}", err)),
+                None,
+            )
+        }
+        .map_err(|err| AudioError::Device(err.to_string()))?;
+        stream
+            .play()
+            .map_err(|err| AudioError::Device(err.to_string()))?;
+
+        Ok(Self {
+            buffer,
+            _stream: stream,
+        })
+    }
+}
+
+#[cfg(feature = "audio-device")]
+impl Output for DeviceOutput {
+    fn write(&mut self, samples: &[f32]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.extend(samples);
+        let excess = buffer.len().saturating_sub(Self::MAX_BUFFERED);
+        // Keep channels in order by dropping whole frames
+        buffer.drain(..excess - excess % 2);
+    }
+}
@@ -0,0 +1,269 @@
+//! Reading and writing WAV files
+use super::{AudioError, Sound};
+
+fn invalid(message: &str) -> AudioError {
+    AudioError::Invalid(message.to_owned())
+}
+
+/// Decode PCM (8, 16, 24 or 32 bit) or float (32 bit) WAV data
+pub fn decode(bytes: &[u8]) -> Result<Sound, AudioError> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+
+    let u16_at = |data: &[u8], offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let u32_at = |data: &[u8], offset: usize| {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    };
+
+    let mut format = None;
+    let mut data = None;
+    let mut offset: usize = 12;
+    while offset
+        .checked_add(8)
+        .is_some_and(|header_end| header_end <= bytes.len())
+    {
+        let id = &bytes[offset..offset + 4];
+        let size = u32_at(bytes, offset + 4) as usize;
+        // Sizes can be anything, and a usize is only 32 bits on wasm
+        let end = (offset + 8).checked_add(size);
+        // Streamed files might not know the size of the last chunk, so it's cut at the end of the file
+        let chunk = &bytes[offset + 8..end.map_or(bytes.len(), |end| end.min(bytes.len()))];
+        match id {
+            b"fmt " => {
+                if chunk.len() < 16 {
+                    return Err(invalid("format chunk is too short"));
+                }
+                let mut tag = u16_at(chunk, 0);
+                // WAVE_FORMAT_EXTENSIBLE keeps the real format in the sub-format GUID
+                if tag == 0xFFFE && chunk.len() >= 26 {
+                    tag = u16_at(chunk, 24);
+                }
+                format = Some((tag, u16_at(chunk, 2), u32_at(chunk, 4), u16_at(chunk, 14)));
+            }
+            b"data" => data = Some(chunk),
+            _ => (),
+        }
+        // Chunks are padded to an even size. A chunk that ends past the addressable range is the last one
+        match end.and_then(|end| end.checked_add(size % 2)) {
+            Some(next) => offset = next,
+            None => break,
+        }
+    }
+
+    let (tag, channels, sample_rate, bits) = format.ok_or_else(|| invalid("no format chunk"))?;
+    let data = data.ok_or_else(|| invalid("no data chunk"))?;
+    if channels == 0 || sample_rate == 0 {
+        return Err(invalid("no channels or zero sample rate"));
+    }
+    let samples = match (tag, bits) {
+        (1, 8) => data
+            .iter()
+            .map(|sample| (*sample as f32 - 128.0) / 128.0)
+            .collect(),
+        (1, 16) => data
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0)
+            .collect(),
+        (1, 24) => data
+            .chunks_exact(3)
+            .map(|sample| {
+                i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) as f32 / 2147483648.0
+            })
+            .collect(),
+        (1, 32) => data
+            .chunks_exact(4)
+            .map(|sample| {
+                i32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f32
+                    / 2147483648.0
+            })
+            .collect(),
+        (3, 32) => data
+            .chunks_exact(4)
+            .map(|sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]))
+            .collect(),
+        _ => {
+            return Err(AudioError::Invalid(format!(
+                "unsupported format {} with {} bits per sample",
+                tag, bits
+            )))
+        }
+    };
+    Ok(Sound {
+        sample_rate,
+        channels,
+        samples,
+    })
+}
+
+/// Header of a 16 bit PCM WAV file with `frames` frames
+pub fn header(channels: u16, sample_rate: u32, frames: u32) -> [u8; 44] {
+    let block_align = channels * 2;
+    let data_size = frames * block_align as u32;
+    let mut header = [0; 44];
+    let fields: [&[u8]; 12] = [
+        b"RIFF",
+        &(36 + data_size).to_le_bytes(),
+        b"WAVEfmt ",
+        &16_u32.to_le_bytes(),
+        &1_u16.to_le_bytes(),
+        &channels.to_le_bytes(),
+        &sample_rate.to_le_bytes(),
+        &(sample_rate * block_align as u32).to_le_bytes(),
+        &block_align.to_le_bytes(),
+        &16_u16.to_le_bytes(),
+        b"data",
+        &data_size.to_le_bytes(),
+    ];
+    let mut offset = 0;
+    for field in fields {
+        header[offset..offset + field.len()].copy_from_slice(field);
+        offset += field.len();
+    }
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16 bit PCM file holding `samples`
+    fn wav(channels: u16, samples: &[i16]) -> Vec<u8> {
+        let frames = samples.len() as u32 / channels as u32;
+        let mut bytes = header(channels, 22050, frames).to_vec();
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// RIFF file made of `chunks`, without checking their sizes
+    fn riff(chunks: &[(&[u8; 4], u32, &[u8])]) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (id, size, data) in chunks {
+            bytes.extend_from_slice(*id);
+            bytes.extend_from_slice(&size.to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn format(tag: u16, channels: u16, bits: u16) -> Vec<u8> {
+        header(channels, 8000, 0)[20..36]
+            .iter()
+            .copied()
+            .enumerate()
+            .map(|(index, byte)| match index {
+                0 => tag.to_le_bytes()[0],
+                1 => tag.to_le_bytes()[1],
+                14 => bits.to_le_bytes()[0],
+                15 => bits.to_le_bytes()[1],
+                _ => byte,
+            })
+            .collect()
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        match decode(bytes) {
+            Err(AudioError::Invalid(message)) => message,
+            other => panic!("expected an invalid sound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decodes_its_own_header() {
+        let sound = decode(&wav(2, &[0, 16384, -32768, 32767])).unwrap();
+        assert_eq!(sound.sample_rate, 22050);
+        assert_eq!(sound.channels, 2);
+        assert_eq!(sound.frames(), 2);
+        assert_eq!(sound.samples, vec![0.0, 0.5, -1.0, 32767.0 / 32768.0]);
+    }
+
+    #[test]
+    fn decodes_other_formats() {
+        let sound = decode(&riff(&[
+            (b"fmt ", 16, &format(1, 1, 8)),
+            (b"data", 2, &[128, 0]),
+        ]))
+        .unwrap();
+        assert_eq!(sound.samples, vec![0.0, -1.0]);
+
+        let sound = decode(&riff(&[
+            (b"fmt ", 16, &format(3, 1, 32)),
+            (b"data", 4, &0.25_f32.to_le_bytes()),
+        ]))
+        .unwrap();
+        assert_eq!(sound.samples, vec![0.25]);
+    }
+
+    #[test]
+    fn skips_padding_of_odd_chunks() {
+        let sound = decode(&riff(&[
+            (b"LIST", 3, &[1, 2, 3, 0]),
+            (b"fmt ", 16, &format(1, 1, 8)),
+            (b"data", 1, &[255]),
+        ]))
+        .unwrap();
+        assert_eq!(sound.samples, vec![127.0 / 128.0]);
+    }
+
+    #[test]
+    fn cuts_truncated_data() {
+        let mut bytes = wav(1, &[16384, 16384, 16384]);
+        bytes.truncate(bytes.len() - 1);
+        let sound = decode(&bytes).unwrap();
+        assert_eq!(sound.samples, vec![0.5, 0.5]);
+
+        // Streamed files can end with a data chunk of unknown size
+        let sound = decode(&riff(&[
+            (b"fmt ", 16, &format(1, 1, 8)),
+            (b"data", u32::MAX, &[128, 128]),
+        ]))
+        .unwrap();
+        assert_eq!(sound.samples, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert_eq!(error(b""), "not a WAV file");
+        assert_eq!(error(b"RIFF\0\0\0\0AVI LIST"), "not a WAV file");
+        assert_eq!(error(&riff(&[])), "no format chunk");
+        assert_eq!(
+            error(&riff(&[(b"fmt ", 16, &format(1, 1, 16))])),
+            "no data chunk"
+        );
+        assert_eq!(
+            error(&riff(&[(b"fmt ", 8, &[1, 0, 1, 0, 0, 0, 0, 0])])),
+            "format chunk is too short"
+        );
+        assert_eq!(
+            error(&riff(&[
+                (b"fmt ", 16, &format(1, 0, 16)),
+                (b"data", 0, &[])
+            ])),
+            "no channels or zero sample rate"
+        );
+        assert_eq!(
+            error(&riff(&[(b"fmt ", 16, &format(2, 1, 4)), (b"data", 0, &[])])),
+            "unsupported format 2 with 4 bits per sample"
+        );
+    }
+
+    #[test]
+    fn survives_huge_chunk_sizes() {
+        // The chunk claims to cover the format chunk that follows it
+        let mut bytes = riff(&[(b"junk", u32::MAX - 1, &[])]);
+        bytes.extend_from_slice(&riff(&[(b"fmt ", 16, &format(1, 1, 8))])[12..]);
+        assert_eq!(error(&bytes), "no format chunk");
+
+        // A header cut in the middle
+        let mut bytes = wav(1, &[0]);
+        bytes.truncate(40);
+        assert_eq!(error(&bytes), "no data chunk");
+    }
+}
@@ -0,0 +1,475 @@
+//! Collision detection against a grid built from a level
+use super::*;
+
+/// How far bodies stop before an obstacle, so float errors don't push them into it
+pub const SKIN: f32 = 0.001;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cell {
+    Empty,
+    Solid,
+    Slippery,
+}
+
+/// Earliest contact of a moving box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contact {
+    /// Fraction of the motion done before the contact, 0..=1
+    pub time: f32,
+    /// Normal of the surface that was hit, pointing away from it
+    pub normal: Vec2,
+    pub slippery: bool,
+}
+
+/// First thing a ray hit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    pub position: Vec2,
+    /// Normal of the surface that was hit, pointing away from it
+    pub normal: Vec2,
+    pub distance: f32,
+    /// Position and kind of the cell that was hit, None for platforms
+    pub cell: Option<(IVec2, Cell)>,
+}
+
+/// Collision shapes of a level: a grid of solid cells and platforms as boxes.
+/// Outside of the level counts as solid. Has to be rebuilt when tiles change
+pub struct CollisionGrid {
+    pub size: UVec2,
+    pub grid_size: UVec2,
+    pub cells: Vec<Cell>,
+    pub platforms: Vec<(Vec2, Vec2)>,
+}
+
+impl CollisionGrid {
+    pub fn new(level: &world::Level, tiles: &tiles::TileTable) -> Self {
+        let size = level.solid.size;
+        let width = size.x as usize;
+        let mut cells = Vec::with_capacity(level.solid.tiles.len());
+        for (index, tile) in level.solid.tiles.iter().enumerate() {
+            let above = index
+                .checked_sub(width)
+                .map(|index| level.solid.tiles[index]);
+            let mut cell = match tile {
+                world::SolidTile::Ground => Cell::Solid,
+                // Only the top of a lamp is solid
+                world::SolidTile::Lamp if above != Some(world::SolidTile::Lamp) => Cell::Solid,
+                _ => Cell::Empty,
+            };
+            for tile in [&level.background.tiles, &level.foreground.tiles]
+                .into_iter()
+                .filter_map(|layer| layer.get(index)?.as_ref())
+            {
+                let properties = tiles.get(tile.position);
+                if properties.solid && properties.slippery {
+                    cell = Cell::Slippery;
+                } else if properties.solid && cell == Cell::Empty {
+                    cell = Cell::Solid;
+                }
+            }
+            cells.push(cell);
+        }
+
+        let mut grid = Self {
+            size,
+            grid_size: level.solid.grid_size(),
+            cells,
+            platforms: Vec::new(),
+        };
+        grid.update_platforms(level);
+        grid
+    }
+
+    /// Platforms move, so unlike tiles, they have to be updated every frame
+    pub fn update_platforms(&mut self, level: &world::Level) {
+        self.platforms.clear();
+        self.platforms.extend(
+            level
+                .entities
+                .entities()
+                .iter()
+                .filter(|entity| matches!(entity.entity, world::Entity::Platform(_)))
+                .map(|entity| {
+                    let tl = entity.top_left();
+                    (tl, tl + entity.size.into_f32())
+                }),
+        );
+    }
+
+    pub fn get(&self, position: IVec2) -> Cell {
+        if position.x < 0
+            || position.y < 0
+            || position.x >= self.size.x as i32
+            || position.y >= self.size.y as i32
+        {
+            return Cell::Solid;
+        }
+        self.cells[(position.x + position.y * self.size.x as i32) as usize]
+    }
+
+    /// Cell that contains a point
+    pub fn cell_at(&self, point: Vec2) -> IVec2 {
+        let grid_size = self.grid_size.into_f32();
+        IVec2::new(
+            (point.x / grid_size.x).floor() as _,
+            (point.y / grid_size.y).floor() as _,
+        )
+    }
+
+    /// Cells touched by a box, at most one cell past the level on each side.
+    /// Outside of the level is all solid, so cells further away never matter
+    fn cell_rect(&self, tl: Vec2, br: Vec2) -> impl Iterator<Item = IVec2> {
+        let grid_size = self.grid_size.into_f32();
+        let tl = IVec2::new(
+            ((tl.x / grid_size.x).floor() as i32).max(-1),
+            ((tl.y / grid_size.y).floor() as i32).max(-1),
+        );
+        let br = IVec2::new(
+            ((br.x / grid_size.x).ceil() as i32).min(self.size.x as i32 + 1),
+            ((br.y / grid_size.y).ceil() as i32).min(self.size.y as i32 + 1),
+        );
+        (tl.y..br.y).flat_map(move |y| (tl.x..br.x).map(move |x| IVec2::new(x, y)))
+    }
+
+    fn cell_box(&self, position: IVec2) -> (Vec2, Vec2) {
+        let grid_size = self.grid_size.into_f32();
+        let tl = Vec2::new(
+            position.x as f32 * grid_size.x,
+            position.y as f32 * grid_size.y,
+        );
+        (tl, tl + grid_size)
+    }
+
+    /// Whether a box overlaps anything solid. Touching doesn't count
+    pub fn overlaps(&self, (tl, br): (Vec2, Vec2)) -> bool {
+        let overlap = |(other_tl, other_br): (Vec2, Vec2)| {
+            br.x > other_tl.x && br.y > other_tl.y && tl.x < other_br.x && tl.y < other_br.y
+        };
+        self.cell_rect(tl, br)
+            .any(|position| self.get(position) != Cell::Empty && overlap(self.cell_box(position)))
+            || self.platforms.iter().copied().any(overlap)
+    }
+
+    /// Solid cells of the level overlapping a box, with their positions
+    pub fn overlapping_cells(
+        &self,
+        tl: Vec2,
+        br: Vec2,
+    ) -> impl Iterator<Item = (IVec2, Cell)> + '_ {
+        self.cell_rect(tl, br)
+            .filter(|position| {
+                position.x >= 0
+                    && position.y >= 0
+                    && position.x < self.size.x as i32
+                    && position.y < self.size.y as i32
+            })
+            .map(|position| (position, self.get(position)))
+            .filter(|(_, cell)| *cell != Cell::Empty)
+    }
+
+    /// First thing a ray hits within `max_distance`. `direction` has to be normalized and
+    /// `max_distance` finite. Like with [`CollisionGrid::sweep`], obstacles the ray starts inside of are ignored
+    pub fn raycast(&self, origin: Vec2, direction: Vec2, max_distance: f32) -> Option<RayHit> {
+        let mut first = self.traverse(origin, direction, max_distance);
+        for platform in &self.platforms {
+            let Some((time, normal)) =
+                sweep_box((origin, origin), direction * max_distance, *platform)
+            else {
+                continue;
+            };
+            let distance = time * max_distance;
+            if first.is_none_or(|first| distance < first.distance) {
+                first = Some(RayHit {
+                    position: origin + direction * distance,
+                    normal,
+                    distance,
+                    cell: None,
+                });
+            }
+        }
+        first
+    }
+
+    /// Walk the cells along a ray until one is not empty. Stops at the first one past the
+    /// edge of the level, as outside of it is solid
+    fn traverse(&self, origin: Vec2, direction: Vec2, max_distance: f32) -> Option<RayHit> {
+        let grid_size = self.grid_size.into_f32();
+        let step = |direction: f32| {
+            if direction > 0.0 {
+                1
+            } else if direction < 0.0 {
+                -1
+            } else {
+                0
+            }
+        };
+        let step = IVec2::new(step(direction.x), step(direction.y));
+        // Distance along the ray to the first boundary on each axis, and between boundaries
+        let boundary = |cell: i32, step: i32, size: f32, origin: f32, direction: f32| {
+            if step == 0 {
+                return f32::INFINITY;
+            }
+            (cell.saturating_add((step > 0) as i32) as f32 * size - origin) / direction
+        };
+        let mut cell = self.cell_at(origin);
+        let mut next = Vec2::new(
+            boundary(cell.x, step.x, grid_size.x, origin.x, direction.x),
+            boundary(cell.y, step.y, grid_size.y, origin.y, direction.y),
+        );
+        let delta = Vec2::new(
+            grid_size.x / direction.x.abs(),
+            grid_size.y / direction.y.abs(),
+        );
+        loop {
+            let (distance, normal) = if next.x < next.y {
+                let distance = next.x;
+                cell.x = cell.x.saturating_add(step.x);
+                next.x += delta.x;
+                (distance, Vec2::new(-step.x as f32, 0.0))
+            } else {
+                let distance = next.y;
+                cell.y = cell.y.saturating_add(step.y);
+                next.y += delta.y;
+                (distance, Vec2::new(0.0, -step.y as f32))
+            };
+            // NaN when the origin is not finite
+            if distance.is_nan() || distance > max_distance {
+                return None;
+            }
+            let kind = self.get(cell);
+            if kind != Cell::Empty {
+                let distance = distance.max(0.0);
+                return Some(RayHit {
+                    position: origin + direction * distance,
+                    normal,
+                    distance,
+                    cell: Some((cell, kind)),
+                });
+            }
+        }
+    }
+
+    /// Find the first contact of a box moving by `motion`. Obstacles the box is already inside of are ignored
+    pub fn sweep(&self, (tl, br): (Vec2, Vec2), motion: Vec2) -> Option<Contact> {
+        let area_tl = tl + Vec2::new(motion.x.min(0.0), motion.y.min(0.0));
+        let area_br = br + Vec2::new(motion.x.max(0.0), motion.y.max(0.0));
+
+        let mut first: Option<Contact> = None;
+        let mut check = |obstacle: (Vec2, Vec2), slippery: bool| {
+            if let Some((time, normal)) = sweep_box((tl, br), motion, obstacle) {
+                if first.is_none_or(|first| time < first.time) {
+                    first = Some(Contact {
+                        time,
+                        normal,
+                        slippery,
+                    });
+                }
+            }
+        };
+        for position in self.cell_rect(area_tl, area_br) {
+            match self.get(position) {
+                Cell::Empty => (),
+                cell => check(self.cell_box(position), cell == Cell::Slippery),
+            }
+        }
+        for platform in &self.platforms {
+            check(*platform, false);
+        }
+        first
+    }
+}
+
+/// Time of impact and normal of box `(tl, br)` moving by `motion` into `obstacle`
+fn sweep_box((tl, br): (Vec2, Vec2), motion: Vec2, obstacle: (Vec2, Vec2)) -> Option<(f32, Vec2)> {
+    fn axis(
+        min: f32,
+        max: f32,
+        obstacle_min: f32,
+        obstacle_max: f32,
+        motion: f32,
+    ) -> Option<(f32, f32)> {
+        if motion > 0.0 {
+            Some(((obstacle_min - max) / motion, (obstacle_max - min) / motion))
+        } else if motion < 0.0 {
+            Some(((obstacle_max - min) / motion, (obstacle_min - max) / motion))
+        } else if max > obstacle_min && min < obstacle_max {
+            Some((f32::NEG_INFINITY, f32::INFINITY))
+        } else {
+            None
+        }
+    }
+
+    let (entry_x, exit_x) = axis(tl.x, br.x, obstacle.0.x, obstacle.1.x, motion.x)?;
+    let (entry_y, exit_y) = axis(tl.y, br.y, obstacle.0.y, obstacle.1.y, motion.y)?;
+    let entry = entry_x.max(entry_y);
+    let exit = exit_x.min(exit_y);
+    // Penetrations shallower than the skin are float errors, deeper ones mean the box is already inside
+    if entry >= exit || entry > 1.0 || entry * motion.magnitude() < -SKIN {
+        return None;
+    }
+    let normal = if entry_x > entry_y {
+        Vec2::new(-motion.x.signum(), 0.0)
+    } else {
+        Vec2::new(0.0, -motion.y.signum())
+    };
+    Some((entry.max(0.0), normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grid of 16x16 cells: `#` is solid, `~` is slippery, anything else is empty
+    fn grid(rows: &[&str]) -> CollisionGrid {
+        let cells = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|cell| match cell {
+                '#' => Cell::Solid,
+                '~' => Cell::Slippery,
+                _ => Cell::Empty,
+            })
+            .collect();
+        CollisionGrid {
+            size: UVec2::new(rows[0].len() as u32, rows.len() as u32),
+            grid_size: UVec2::new(16, 16),
+            cells,
+            platforms: Vec::new(),
+        }
+    }
+
+    fn square(x: f32, y: f32, size: f32) -> (Vec2, Vec2) {
+        (Vec2::new(x, y), Vec2::new(x + size, y + size))
+    }
+
+    #[test]
+    fn sweep_box_hits_corner() {
+        let hit = sweep_box(
+            square(0.0, 0.0, 16.0),
+            Vec2::new(32.0, 32.0),
+            square(32.0, 32.0, 16.0),
+        );
+        let (time, normal) = hit.unwrap();
+        assert_eq!(time, 0.5);
+        assert!(normal == Vec2::new(-1.0, 0.0) || normal == Vec2::new(0.0, -1.0));
+    }
+
+    #[test]
+    fn sweep_box_misses_past_corner() {
+        let hit = sweep_box(
+            square(0.0, 0.0, 16.0),
+            Vec2::new(32.0, 0.0),
+            square(32.0, 16.0, 16.0),
+        );
+        assert_eq!(hit, None);
+    }
+
+    #[test]
+    fn sweep_box_ignores_overlapping_obstacles() {
+        let inside = sweep_box(
+            square(0.0, 0.0, 16.0),
+            Vec2::new(8.0, 0.0),
+            square(8.0, 0.0, 16.0),
+        );
+        assert_eq!(inside, None);
+
+        // Touching is not overlapping
+        let touching = sweep_box(
+            square(0.0, 0.0, 16.0),
+            Vec2::new(8.0, 0.0),
+            square(16.0, 0.0, 16.0),
+        );
+        assert_eq!(touching, Some((0.0, Vec2::new(-1.0, 0.0))));
+    }
+
+    #[test]
+    fn sweep_lands_on_floor() {
+        let grid = grid(&["...", "...", "###"]);
+        let contact = grid
+            .sweep(square(16.0, 0.0, 16.0), Vec2::new(0.0, 32.0))
+            .unwrap();
+        assert_eq!(contact.time, 0.5);
+        assert_eq!(contact.normal, Vec2::new(0.0, -1.0));
+        assert!(!contact.slippery);
+    }
+
+    #[test]
+    fn sweep_reports_slippery_walls() {
+        let grid = grid(&["..~", "..~", "###"]);
+        let contact = grid
+            .sweep(square(0.0, 0.0, 16.0), Vec2::new(32.0, 0.0))
+            .unwrap();
+        assert_eq!(contact.time, 0.5);
+        assert_eq!(contact.normal, Vec2::new(-1.0, 0.0));
+        assert!(contact.slippery);
+    }
+
+    #[test]
+    fn sweep_hits_corner_of_a_cell() {
+        let grid = grid(&["....", "....", "..#.", "...."]);
+        let contact = grid
+            .sweep(square(0.0, 0.0, 16.0), Vec2::new(32.0, 32.0))
+            .unwrap();
+        assert_eq!(contact.time, 0.5);
+    }
+
+    #[test]
+    fn sweep_ignores_cells_it_starts_in() {
+        let grid = grid(&["#..", "...", "..."]);
+        let contact = grid.sweep(square(4.0, 4.0, 8.0), Vec2::new(16.0, 0.0));
+        assert_eq!(contact, None);
+    }
+
+    #[test]
+    fn outside_of_the_level_is_solid() {
+        let grid = grid(&["...", "...", "..."]);
+        let contact = grid
+            .sweep(square(8.0, 8.0, 8.0), Vec2::new(-1e9, 0.0))
+            .unwrap();
+        assert_eq!(contact.normal, Vec2::new(1.0, 0.0));
+        assert!(grid.overlaps(square(-1e9, -1e9, 2e9)));
+        assert_eq!(
+            grid.overlapping_cells(Vec2::ZERO, Vec2::new(48.0, 48.0))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn raycast_stops_at_first_cell() {
+        let grid = grid(&["....", "..#~", "...."]);
+        let hit = grid
+            .raycast(Vec2::new(8.0, 24.0), Vec2::new(1.0, 0.0), 100.0)
+            .unwrap();
+        assert_eq!(hit.distance, 24.0);
+        assert_eq!(hit.position, Vec2::new(32.0, 24.0));
+        assert_eq!(hit.normal, Vec2::new(-1.0, 0.0));
+        assert_eq!(hit.cell, Some((IVec2::new(2, 1), Cell::Solid)));
+
+        assert_eq!(
+            grid.raycast(Vec2::new(8.0, 24.0), Vec2::new(1.0, 0.0), 10.0),
+            None
+        );
+    }
+
+    #[test]
+    fn raycast_ends_at_the_edge_of_the_level() {
+        let grid = grid(&["....", "....", "...."]);
+        let hit = grid
+            .raycast(Vec2::new(8.0, 8.0), Vec2::new(0.0, 1.0), 1e30)
+            .unwrap();
+        assert_eq!(hit.distance, 40.0);
+        assert_eq!(hit.cell, Some((IVec2::new(0, 3), Cell::Solid)));
+    }
+
+    #[test]
+    fn raycast_hits_platforms() {
+        let mut grid = grid(&["....", "....", "...."]);
+        grid.platforms.push(square(32.0, 0.0, 16.0));
+        let hit = grid
+            .raycast(Vec2::new(8.0, 8.0), Vec2::new(1.0, 0.0), 100.0)
+            .unwrap();
+        assert_eq!(hit.distance, 24.0);
+        assert_eq!(hit.cell, None);
+    }
+}
@@ -1,12 +1,87 @@
+use serde::{Deserialize, Serialize};
 use speedy2d::dimen::*;
 use speedy2d::window::{MouseButton, VirtualKeyCode};
 use std::collections::HashMap;
 
+/// Keys the game reacts to, with names they have in replays
+pub const KEYS: &[(VirtualKeyCode, &str)] = &[
+    (VirtualKeyCode::A, "a"),
+    (VirtualKeyCode::C, "c"),
+    (VirtualKeyCode::D, "d"),
+    (VirtualKeyCode::H, "h"),
+    (VirtualKeyCode::J, "j"),
+    (VirtualKeyCode::K, "k"),
+    (VirtualKeyCode::L, "l"),
+    (VirtualKeyCode::S, "s"),
+    (VirtualKeyCode::W, "w"),
+    (VirtualKeyCode::Left, "left"),
+    (VirtualKeyCode::Right, "right"),
+    (VirtualKeyCode::Up, "up"),
+    (VirtualKeyCode::Down, "down"),
+    (VirtualKeyCode::Space, "space"),
+    (VirtualKeyCode::Tab, "tab"),
+    (VirtualKeyCode::Grave, "grave"),
+    (VirtualKeyCode::Return, "enter"),
+    (VirtualKeyCode::Backspace, "backspace"),
+    (VirtualKeyCode::Delete, "delete"),
+    (VirtualKeyCode::Home, "home"),
+    (VirtualKeyCode::End, "end"),
+    (VirtualKeyCode::PageUp, "page_up"),
+    (VirtualKeyCode::PageDown, "page_down"),
+    (VirtualKeyCode::Escape, "escape"),
+    (VirtualKeyCode::F5, "f5"),
+    (VirtualKeyCode::F9, "f9"),
+];
+
+pub const MOUSE_BUTTONS: &[(MouseButton, &str)] = &[
+    (MouseButton::Left, "left"),
+    (MouseButton::Middle, "middle"),
+    (MouseButton::Right, "right"),
+];
+
+/// State of the modifier keys. Unlike [`speedy2d::window::ModifiersState`], can be constructed (and replayed)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub logo: bool,
+}
+
+impl Modifiers {
+    pub fn ctrl(&self) -> bool {
+        self.ctrl
+    }
+
+    pub fn alt(&self) -> bool {
+        self.alt
+    }
+
+    pub fn shift(&self) -> bool {
+        self.shift
+    }
+
+    pub fn logo(&self) -> bool {
+        self.logo
+    }
+}
+
+impl From<&speedy2d::window::ModifiersState> for Modifiers {
+    fn from(state: &speedy2d::window::ModifiersState) -> Self {
+        Self {
+            ctrl: state.ctrl(),
+            alt: state.alt(),
+            shift: state.shift(),
+            logo: state.logo(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Controls {
     pub pressed: HashMap<VirtualKeyCode, bool>,
     pub jpressed: HashMap<VirtualKeyCode, bool>,
-    pub mods: speedy2d::window::ModifiersState,
+    pub mods: Modifiers,
     pub mouse_pos: Vec2,
     pub mouse_buttons: HashMap<MouseButton, bool>,
     pub typed_text: String,
@@ -85,4 +160,17 @@ impl Controls {
             || self.jpressed(VirtualKeyCode::Space)
             || self.jpressed(VirtualKeyCode::Return)
     }
+
+    pub fn quick_save(&self) -> bool {
+        self.jpressed(VirtualKeyCode::F5)
+    }
+
+    pub fn quick_load(&self) -> bool {
+        self.jpressed(VirtualKeyCode::F9)
+    }
+
+    /// Interrupt the Python code that runs next, see [`crate::watch::interpreter::watchdog::Budget`]
+    pub fn interrupt(&self) -> bool {
+        self.mods.ctrl() && self.jpressed(VirtualKeyCode::C)
+    }
 }
@@ -0,0 +1,580 @@
+use super::*;
+use watch::interpreter::events::Event;
+
+/// Shown when the last level is finished
+const CAMPAIGN_COMPLETE: &[&str] = &["You did it! It only took you $TIME"];
+
+/// Whole game state, independent of the window. [`Game::step`] advances the simulation
+/// and [`Game::draw`] renders it, so the game can also be driven without a window
+pub struct Game {
+    /// Size of the window in pixels
+    pub viewport: Vec2,
+    pub camera: Vec2,
+
+    pub level_index: usize,
+    pub introduced: bool,
+    pub dialogue: &'static [&'static str],
+
+    pub world: levels::World,
+    /// Properties of tiles in the tileset of [`Game::world`]. Not part of it, as `world.rs` is generated
+    pub tiles: tiles::TileTable,
+    /// Rules of the levels of [`Game::world`], for the same reason
+    pub rules: rules::Rules,
+    pub player: Player,
+    pub watch: Watch,
+    /// Collision grid of the current level, built when needed
+    pub collision: Option<collision::CollisionGrid>,
+    /// Sounds of the game. Mixed by whoever runs it, see [`audio::Mixer::mix`]
+    pub audio: audio::Mixer,
+
+    pub particles: Vec<Particle>,
+    pub weather_particle_timer: f32,
+
+    pub timer: Option<f32>,
+    pub finished: bool,
+
+    /// Seed of [`Game::rng`], so runs can be reproduced
+    pub seed: u64,
+    pub rng: rand::rngs::StdRng,
+}
+
+impl Game {
+    pub fn new(world: levels::World, viewport: Vec2) -> Self {
+        let player = Player::new(get_player_start_position(&world[0].entities));
+        let mut audio = audio::Mixer::default();
+        audio.play_music(std::sync::Arc::new(audio::music()), 0.5);
+        Self {
+            viewport,
+            camera: Vec2::ZERO,
+
+            level_index: 0,
+            introduced: false,
+            dialogue: &[],
+
+            world,
+            tiles: tiles::TileTable::builtin(),
+            rules: rules::Rules::default(),
+            player,
+            watch: Watch::default(),
+            collision: None,
+            audio,
+            particles: Vec::new(),
+            weather_particle_timer: 0.0,
+
+            timer: None,
+            finished: false,
+
+            seed: 0,
+            rng: rand::SeedableRng::seed_from_u64(0),
+        }
+        .with_seed(rand::random())
+    }
+
+    /// Use tiles of a world loaded at runtime, instead of the built-in tileset
+    pub fn with_tiles(mut self, tiles: tiles::TileTable) -> Self {
+        self.tiles = tiles;
+        self.collision = None;
+        self
+    }
+
+    /// Use rules of levels of a world loaded at runtime
+    pub fn with_rules(mut self, rules: rules::Rules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self.rng = rand::SeedableRng::seed_from_u64(seed);
+        self.watch.interpreter.seed_random(seed);
+        self
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.viewport.y / 256.0
+    }
+
+    /// Size of the screen in world pixels
+    pub fn screen_size(&self) -> Vec2 {
+        self.viewport / self.scale()
+    }
+
+    pub fn step(&mut self, delta_time: f32, controls: &Controls) {
+        if let Some(timer) = &mut self.timer {
+            if !self.finished {
+                *timer += delta_time;
+            }
+        }
+
+        let scale = self.scale();
+        let screen_size = self.screen_size();
+        let level = &self.world[self.level_index];
+        if !self.watch.open && self.dialogue.is_empty() {
+            let collision = self
+                .collision
+                .get_or_insert_with(|| collision::CollisionGrid::new(level, &self.tiles));
+            collision.update_platforms(level);
+            self.player.update(delta_time, collision, controls);
+            for effect in self.player.effects.drain(..) {
+                let position = self.player.position;
+                let event = match effect {
+                    audio::Effect::Land => Some(Event::Landed(position)),
+                    audio::Effect::Kick => Some(Event::WallKick(position)),
+                    _ => None,
+                };
+                self.watch.interpreter.events.extend(event);
+                self.audio.play_effect(effect);
+            }
+            for entity in level.entities.entities() {
+                if self.player.overlaps(entity) {
+                    match entity.entity {
+                        world::Entity::EndOfTheLevel(_) => {
+                            if self.level_index + 1 < self.world.len() || !self.finished {
+                                self.audio.play_effect(audio::Effect::LevelEnd);
+                                self.watch
+                                    .interpreter
+                                    .events
+                                    .push(Event::LevelFinished(self.level_index, self.timer));
+                            }
+                            if self.level_index + 1 < self.world.len() {
+                                self.level_index += 1;
+                                self.watch
+                                    .interpreter
+                                    .events
+                                    .push(Event::LevelEntered(self.level_index));
+                                self.collision = None;
+                                self.player.teleport(get_player_start_position(
+                                    &self.world[self.level_index].entities,
+                                ));
+                                self.camera = self.player.position
+                                    + self.player.size.into_f32() / 2.0
+                                    - screen_size / 2.0;
+                            } else if !self.finished {
+                                self.finished = true;
+                                self.dialogue = CAMPAIGN_COMPLETE;
+                            }
+                        }
+
+                        world::Entity::Void(_) => {
+                            if self.level_index == 0 {
+                                if !self.introduced {
+                                    self.dialogue = &[
+                                    "Hey!",
+                                    "I'm Void, and you probably have heard of me.",
+                                    "So, I just finished designing this watch...",
+                                    "It's not your usual fitness bracelet. It's something more!",
+                                    "And I want you to test it...",
+                                    "Can you just *run* through this obstacle course I made for you as fast as possible?",
+                                    "You might need to *write* some code to unleash bracelet's full potential...",
+                                    "Your time starts... Now!",
+                                ];
+                                    self.introduced = true;
+                                }
+                            } else {
+                                self.finished = true;
+                                self.dialogue = CAMPAIGN_COMPLETE;
+                                self.watch
+                                    .interpreter
+                                    .events
+                                    .push(Event::LevelFinished(self.level_index, self.timer));
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        let tiles = &self.tiles;
+        let level = &mut self.world.levels[self.level_index];
+        for particle in &mut self.particles {
+            particle.update(level, tiles, delta_time);
+        }
+        self.particles.retain(|particle| particle.lifetime > 0.0);
+
+        self.camera += ((self.player.interpolated_position() + self.player.size.into_f32() / 2.0
+            - screen_size / 2.0)
+            - self.camera)
+            * (1.0 - 0.05_f32.powf(delta_time));
+        self.camera.x = self
+            .camera
+            .x
+            .clamp(0.0, level.pixel_size.x as f32 - screen_size.x);
+        self.camera.y = self
+            .camera
+            .y
+            .clamp(0.0, level.pixel_size.y as f32 - screen_size.y);
+
+        {
+            let weather = self.watch.interpreter.weather;
+            let (pps, particle_color) = match weather {
+                interpreter::pywatch::Weather::Rainy => (
+                    0.1 * level.pixel_size.x as f32,
+                    Color::from_hex_rgb(0x00057F),
+                ),
+                interpreter::pywatch::Weather::Snowy => (
+                    0.1 * level.pixel_size.x as f32,
+                    Color::from_hex_rgb(0xFFFFFF),
+                ),
+                _ => (0.0, Color::MAGENTA),
+            };
+            self.weather_particle_timer += delta_time;
+            while self.weather_particle_timer > 1.0 / pps {
+                self.weather_particle_timer -= 1.0 / pps;
+                self.particles.push(Particle::new(
+                    Vec2::new(
+                        self.rng.gen_range(0.0..level.pixel_size.x as f32),
+                        self.rng.gen_range(-20.0..20.0_f32),
+                    ),
+                    Vec2::ZERO,
+                    ParticleVisual::Color(particle_color, Vec2::new(1.0, 2.5), false),
+                    true,
+                ));
+            }
+            if pps == 0.0 {
+                self.weather_particle_timer = 0.0;
+            }
+        }
+
+        for entity in level.entities.entities_mut() {
+            match &mut entity.entity {
+                world::Entity::EndOfTheLevel(eol) => {
+                    let pps = 100.0;
+                    eol.particle_timer += delta_time;
+                    while eol.particle_timer > 1.0 / pps {
+                        eol.particle_timer -= 1.0 / pps;
+                        let direction = self.rng.gen_range(0.0..std::f32::consts::PI * 2.0);
+                        let velocity = direction.sin_cos();
+                        let velocity =
+                            Vec2::new(velocity.0, velocity.1) * self.rng.gen_range(0.0..100.0);
+                        self.particles.push(Particle::new(
+                            entity.position,
+                            velocity,
+                            ParticleVisual::Color(Color::WHITE, Vec2::new(2.0, 2.0), true),
+                            false,
+                        ));
+                    }
+                }
+                world::Entity::Void(void) => {
+                    let pps = 100.0;
+                    void.particle_timer += delta_time;
+                    while void.particle_timer > 1.0 / pps {
+                        void.particle_timer -= 1.0 / pps;
+                        let direction = self.rng.gen_range(0.0..std::f32::consts::PI * 2.0);
+                        let velocity = direction.sin_cos();
+                        let velocity =
+                            Vec2::new(velocity.0, velocity.1) * self.rng.gen_range(0.0..100.0);
+                        self.particles.push(Particle::new(
+                            entity.position,
+                            velocity,
+                            ParticleVisual::Texture(self.rng.gen_range(0..=1)),
+                            true,
+                        ));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if self.introduced
+            && self.watch.update(
+                delta_time,
+                controls,
+                scale,
+                screen_size,
+                self.level_index,
+                &self.world.identifiers[self.level_index],
+                &self.rules.get(self.level_index),
+                level,
+                tiles,
+                &self.player,
+            )
+        {
+            self.collision = None;
+        }
+        if !self.introduced {
+            // Nothing can listen to them yet
+            self.watch.interpreter.events.clear();
+        }
+        for effect in self.watch.interpreter.effects.drain(..) {
+            self.audio.play_effect(effect);
+        }
+        for (sound, volume) in self.watch.interpreter.played_sounds.drain(..) {
+            self.audio.play(sound, audio::Bus::Watch, volume);
+        }
+
+        if !self.dialogue.is_empty() && controls.dialogue_next() {
+            self.audio.play_effect(audio::Effect::Dialogue);
+            self.dialogue = &self.dialogue[1..];
+            if self.dialogue.is_empty() {
+                self.watch
+                    .interpreter
+                    .events
+                    .push(Event::DialogueFinished(self.level_index));
+                if self.level_index == 0 {
+                    self.timer = Some(0.0);
+                }
+                level
+                    .entities
+                    .entities_mut()
+                    .retain(|entity| !matches!(entity.entity, world::Entity::Void(_)));
+            }
+        }
+    }
+
+    pub fn draw(&mut self, graphics: &mut Graphics2D, assets: &Assets) {
+        let screen_size = self.screen_size();
+        let level = &self.world[self.level_index];
+        let mut camera = Camera {
+            graphics,
+            scale: self.scale(),
+            position: self.camera,
+        };
+
+        {
+            let weather = self.watch.interpreter.weather;
+            camera.graphics.clear_screen(match weather {
+                interpreter::pywatch::Weather::Sunny => level.bg_color,
+                interpreter::pywatch::Weather::Rainy => Color::from_hex_rgb(0x9F9F9F),
+                interpreter::pywatch::Weather::Snowy => Color::from_hex_rgb(0xDADADA),
+            });
+        }
+
+        camera.draw_tiles(screen_size, assets, &level.background);
+        camera.draw_autotile(
+            screen_size,
+            assets,
+            &self.tiles,
+            self.watch.interpreter.weather,
+            &level.solid,
+        );
+        camera.draw_tiles(screen_size, assets, &level.ambient_decorations);
+        for entity in level.entities.entities() {
+            if let world::Entity::Platform(platform) = &entity.entity {
+                let point_true = platform.point_true.into_f32() + Vec2::new(0.5, 0.5);
+                let point_true = (point_true * world::Entities::GRID_SIZE as f32 - camera.position)
+                    * camera.scale;
+                let point_false = platform.point_false.into_f32() + Vec2::new(0.5, 0.5);
+                let point_false = (point_false * world::Entities::GRID_SIZE as f32
+                    - camera.position)
+                    * camera.scale;
+                camera.graphics.draw_line(
+                    point_true,
+                    point_false,
+                    camera.scale,
+                    Color::from_hex_rgb(0x52333f),
+                );
+                camera
+                    .graphics
+                    .draw_circle(point_true, 2.0 * camera.scale, Color::GREEN);
+                camera
+                    .graphics
+                    .draw_circle(point_false, 2.0 * camera.scale, Color::RED);
+                camera.draw_tile(
+                    entity.top_left(),
+                    false,
+                    UVec2::new(5, 0),
+                    UVec2::new(32, 16),
+                    &assets.tileset,
+                    false,
+                    false,
+                );
+                let text = assets.font.layout_text(
+                    &platform.condition,
+                    10.0 * camera.scale,
+                    speedy2d::font::TextOptions::new(),
+                );
+                let size = text.size();
+                camera.graphics.draw_text(
+                    point_true - Vec2::new(size.x * 0.5, size.y),
+                    Color::BLACK,
+                    &text,
+                );
+            }
+        }
+        self.player.draw(&mut camera, assets, self.introduced);
+        camera.draw_tiles(screen_size, assets, &level.foreground);
+        for particle in &self.particles {
+            particle.draw(&mut camera, assets);
+        }
+        if self.introduced {
+            self.watch.draw(&mut camera, assets, screen_size);
+        }
+        if let Some(line) = self.dialogue.first() {
+            let position = self.viewport / 2.0;
+            let text = assets.font.layout_text(
+                &line.replace(
+                    "$TIME",
+                    &self.timer.map_or("???".to_owned(), |time| {
+                        format!(
+                            "{:02}:{:02}:{:01.2}",
+                            (time / 60.0 / 60.0) as i32,
+                            (time / 60.0) as i32 % 60,
+                            time % 60.0
+                        )
+                    }),
+                ),
+                12.0 * camera.scale,
+                speedy2d::font::TextOptions::new()
+                    .with_wrap_to_width(80.0 * camera.scale, speedy2d::font::TextAlignment::Left),
+            );
+            let size = text.size();
+            let border = 5.0;
+            let mut rect_size = size;
+            rect_size.x = rect_size.x.max(80.0 * camera.scale);
+            rect_size.y = rect_size.y.max(60.0 * camera.scale);
+            // * Outer border
+            let mut rect = speedy2d::shape::RoundRect::new(
+                position - rect_size / 2.0 - Vec2::new(1.0, 1.0) * 10.0 * camera.scale,
+                position + rect_size / 2.0 + Vec2::new(1.0, 1.0) * 10.0 * camera.scale,
+                10.0 * camera.scale,
+            );
+            camera
+                .graphics
+                .draw_rounded_rectangle(&rect, Color::from_hex_rgb(0xdfe0e8));
+            // * Inner border
+            rect = speedy2d::shape::RoundRect::new(
+                rect.top_left() + Vec2::new(1.0, 1.0) * border / 2.0 * camera.scale,
+                rect.bottom_right() - Vec2::new(1.0, 1.0) * border / 2.0 * camera.scale,
+                rect.radius() - border / 2.0 * camera.scale,
+            );
+            camera
+                .graphics
+                .draw_rounded_rectangle(&rect, Color::from_hex_rgb(0x686f99));
+            // * Inside
+            rect = speedy2d::shape::RoundRect::new(
+                rect.top_left() + Vec2::new(1.0, 1.0) * border / 2.0 * camera.scale,
+                rect.bottom_right() - Vec2::new(1.0, 1.0) * border / 2.0 * camera.scale,
+                rect.radius() - border / 2.0 * camera.scale,
+            );
+            camera
+                .graphics
+                .draw_rounded_rectangle(&rect, Color::from_hex_rgb(0x3d2936));
+
+            use speedy2d::numeric::RoundFloat;
+            camera
+                .graphics
+                .draw_text((position - size / 2.0).round(), Color::WHITE, &text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use speedy2d::window::VirtualKeyCode::{Down, Left, Right, Space};
+
+    /// Keys held through the first level, for a number of frames at 60 FPS
+    const FIRST_LEVEL: &[(u32, &[VirtualKeyCode])] = &[
+        (48, &[Right]),
+        (6, &[Right, Space]),
+        (42, &[Right]),
+        (6, &[]),
+        (6, &[Left]),
+        (6, &[Left, Space]),
+        (12, &[Right]),
+        (6, &[Right, Space]),
+        (30, &[Right]),
+        (6, &[]),
+        (12, &[Right]),
+        (12, &[]),
+        (54, &[Right]),
+        (18, &[Right, Down]),
+        (186, &[Right]),
+        (6, &[Right, Space]),
+        (6, &[Right]),
+    ];
+
+    fn game() -> Game {
+        let mut game = Game::new(levels::World::load(), Vec2::new(854.0, 480.0)).with_seed(0);
+        // Void's dialogue waits for a key press
+        game.introduced = true;
+        game.timer = Some(0.0);
+        game
+    }
+
+    #[test]
+    fn runs_a_level_headless() {
+        let mut game = game();
+        let mut controls = Controls::default();
+        'level: for (frames, keys) in FIRST_LEVEL {
+            controls.pressed = keys.iter().map(|key| (*key, true)).collect();
+            for _ in 0..*frames {
+                game.step(1.0 / 60.0, &controls);
+                if game.level_index != 0 {
+                    break 'level;
+                }
+            }
+        }
+        assert_eq!(game.level_index, 1);
+        assert!(!game.finished);
+        assert_eq!(
+            game.player.position,
+            get_player_start_position(&game.world[1].entities)
+        );
+        assert!(game.timer.unwrap() > 7.0);
+    }
+
+    #[test]
+    fn python_sees_removed_entities() {
+        let mut game = game();
+        game.watch.interpreter.initialize();
+        game.step(1.0 / 60.0, &Controls::default());
+        game.world[0]
+            .entities
+            .retain(|entity| !matches!(entity.entity, world::Entity::Void(_)));
+        game.step(1.0 / 60.0, &Controls::default());
+        let kinds = game
+            .watch
+            .interpreter
+            .run("print([entity['type'] for entity in watch.level()['entities']])")
+            .unwrap();
+        let expected = game.world[0]
+            .entities
+            .entities()
+            .iter()
+            .map(|entity| match entity.entity {
+                world::Entity::Platform(_) => "'platform'",
+                world::Entity::PlayerStartPosition(_) => "'player_start_position'",
+                world::Entity::EndOfTheLevel(_) => "'end_of_the_level'",
+                world::Entity::Void(_) => "'void'",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, format!("[{}]", expected.join(", ")));
+    }
+
+    #[test]
+    fn games_do_not_share_python_state() {
+        let mut snowy = game();
+        let mut sunny = game();
+        snowy.watch.interpreter.initialize();
+        snowy
+            .watch
+            .interpreter
+            .run("watch.set_weather('snowy')\nwatch.after(0, lambda: print('late'))")
+            .unwrap();
+        for _ in 0..2 {
+            snowy.step(1.0 / 60.0, &Controls::default());
+            sunny.step(1.0 / 60.0, &Controls::default());
+        }
+        assert_eq!(
+            snowy.watch.interpreter.weather,
+            interpreter::pywatch::Weather::Snowy
+        );
+        assert_eq!(
+            sunny.watch.interpreter.weather,
+            interpreter::pywatch::Weather::Sunny
+        );
+        let timers = |game: &Game| {
+            game.watch
+                .interpreter
+                .context
+                .clock
+                .lock()
+                .unwrap()
+                .next_timer
+        };
+        assert_eq!(timers(&snowy), 1);
+        assert_eq!(timers(&sunny), 0);
+    }
+}
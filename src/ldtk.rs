@@ -0,0 +1,481 @@
+//! Runtime loader for LDtk projects. Builds the same structures as the generated
+//! [`levels::World::load`], so levels can be edited without re-running the codegen
+use super::*;
+use levels::World;
+use rules::{LevelRules, Rules};
+use serde::Deserialize;
+use tiles::{TileProperties, TileTable};
+use world::{
+    AmbientDecorations, Background, ColorImpl, EndOfTheLevel, Entity, EntityObject, FlipMode,
+    Foreground, Level, Platform, PlayerStartPosition, Solid, SolidTile, Tile, Void,
+};
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Invalid(String),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to read LDtk project: {}", err),
+            Self::Json(err) => write!(f, "failed to parse LDtk project: {}", err),
+            Self::Invalid(message) => write!(f, "invalid LDtk project: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+macro_rules! invalid {
+    ($($arg:tt)*) => {
+        LoadError::Invalid(format!($($arg)*))
+    };
+}
+
+// * JSON schema (only the parts we use)
+#[derive(Deserialize)]
+struct Project {
+    defs: DefsJson,
+    levels: Vec<LevelJson>,
+}
+
+#[derive(Deserialize)]
+struct DefsJson {
+    layers: Vec<LayerDefJson>,
+    tilesets: Vec<TilesetJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LayerDefJson {
+    identifier: String,
+    tileset_def_uid: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TilesetJson {
+    identifier: String,
+    uid: i64,
+    #[serde(rename = "__cWid")]
+    c_wid: u32,
+    #[serde(rename = "__cHei")]
+    c_hei: u32,
+    tags_source_enum_uid: Option<i64>,
+    #[serde(default)]
+    enum_tags: Vec<EnumTagJson>,
+    #[serde(default)]
+    custom_data: Vec<CustomDataJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EnumTagJson {
+    enum_value_id: String,
+    tile_ids: Vec<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CustomDataJson {
+    tile_id: u32,
+    data: String,
+}
+
+/// Custom data of a tile, links to other tiles in the tileset
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TileDataJson {
+    toggled: Option<[u32; 2]>,
+    frozen: Option<[u32; 2]>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LevelJson {
+    identifier: String,
+    px_wid: u32,
+    px_hei: u32,
+    #[serde(rename = "__bgColor")]
+    bg_color: String,
+    world_x: i64,
+    world_y: i64,
+    world_depth: i64,
+    layer_instances: Option<Vec<LayerJson>>,
+    #[serde(default)]
+    field_instances: Vec<FieldJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LayerJson {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__cWid")]
+    c_wid: u32,
+    #[serde(rename = "__cHei")]
+    c_hei: u32,
+    #[serde(rename = "__gridSize")]
+    grid_size: u32,
+    #[serde(default)]
+    int_grid_csv: Vec<u32>,
+    #[serde(default)]
+    grid_tiles: Vec<TileJson>,
+    #[serde(default)]
+    auto_layer_tiles: Vec<TileJson>,
+    #[serde(default)]
+    entity_instances: Vec<EntityJson>,
+}
+
+#[derive(Deserialize)]
+struct TileJson {
+    src: [u32; 2],
+    f: u8,
+    d: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EntityJson {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    px: [f32; 2],
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    field_instances: Vec<FieldJson>,
+}
+
+#[derive(Deserialize)]
+struct FieldJson {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__value")]
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct PointJson {
+    cx: u32,
+    cy: u32,
+}
+
+// * Loading
+/// Load the levels, the properties of tiles they use and their rules
+pub fn load_file(
+    path: impl AsRef<std::path::Path>,
+) -> Result<(World, TileTable, Rules), LoadError> {
+    load(&std::fs::read_to_string(path)?)
+}
+
+pub fn load(json: &str) -> Result<(World, TileTable, Rules), LoadError> {
+    let project: Project = serde_json::from_str(json)?;
+    let levels = project
+        .levels
+        .iter()
+        .map(load_level)
+        .collect::<Result<Vec<_>, _>>()?;
+    if levels.is_empty() {
+        return Err(invalid!("project has no levels"));
+    }
+    let rules = Rules {
+        levels: project
+            .levels
+            .iter()
+            .map(load_rules)
+            .collect::<Result<_, _>>()?,
+    };
+    let identifiers = project
+        .levels
+        .iter()
+        .map(|level| level.identifier.clone())
+        .collect();
+    Ok((
+        World {
+            levels,
+            identifiers,
+        },
+        load_tile_table(&project.defs)?,
+        rules,
+    ))
+}
+
+/// Read rules from level fields. Fields that are not there or not set keep their defaults
+fn load_rules(level: &LevelJson) -> Result<LevelRules, LoadError> {
+    let field = |identifier: &str| {
+        level
+            .field_instances
+            .iter()
+            .find(|field| field.identifier == identifier)
+            .map(|field| &field.value)
+            .filter(|value| !value.is_null())
+    };
+    let mut rules = LevelRules::default();
+    if let Some(value) = field("door_radius") {
+        rules.door_radius = value
+            .as_f64()
+            .ok_or_else(|| invalid!("door_radius of level {} is not a number", level.identifier))?
+            as f32;
+    }
+    Ok(rules)
+}
+
+/// Read tile properties from enum tags and custom data of the tileset used by the Background layer.
+/// If the tileset isn't tagged, properties of the built-in tileset are used
+fn load_tile_table(defs: &DefsJson) -> Result<TileTable, LoadError> {
+    let uid = defs
+        .layers
+        .iter()
+        .find(|layer| layer.identifier == "Background")
+        .and_then(|layer| layer.tileset_def_uid)
+        .ok_or_else(|| invalid!("Background layer has no tileset"))?;
+    let tileset = defs
+        .tilesets
+        .iter()
+        .find(|tileset| tileset.uid == uid)
+        .ok_or_else(|| invalid!("tileset {} does not exist", uid))?;
+    if tileset.tags_source_enum_uid.is_none() {
+        return Ok(TileTable::builtin());
+    }
+
+    let mut table = TileTable::new(UVec2::new(tileset.c_wid, tileset.c_hei));
+    fn properties<'a>(
+        table: &'a mut TileTable,
+        tileset: &TilesetJson,
+        id: u32,
+    ) -> Result<&'a mut TileProperties, LoadError> {
+        table
+            .get_mut(UVec2::new(id % tileset.c_wid, id / tileset.c_wid))
+            .ok_or_else(|| invalid!("tile {} is outside of {}", id, tileset.identifier))
+    }
+    for tag in &tileset.enum_tags {
+        for &id in &tag.tile_ids {
+            let properties = properties(&mut table, tileset, id)?;
+            match tag.enum_value_id.as_str() {
+                "Solid" => properties.solid = true,
+                "Slippery" => properties.slippery = true,
+                "Door" => properties.door = true,
+                "Freezable" => properties.freezable = true,
+                "BlocksParticles" => properties.blocks_particles = true,
+                _ => return Err(invalid!("unknown tile tag {}", tag.enum_value_id)),
+            }
+        }
+    }
+    for custom_data in &tileset.custom_data {
+        let data: TileDataJson = serde_json::from_str(&custom_data.data)?;
+        let properties = properties(&mut table, tileset, custom_data.tile_id)?;
+        properties.toggled = data.toggled.map(|[x, y]| UVec2::new(x, y));
+        properties.frozen = data.frozen.map(|[x, y]| UVec2::new(x, y));
+    }
+    for (id, properties) in table.properties.iter().enumerate() {
+        if properties.door && properties.toggled.is_none() {
+            return Err(invalid!("door tile {} has no \"toggled\" tile", id));
+        }
+        if properties.freezable && properties.frozen.is_none() {
+            return Err(invalid!("freezable tile {} has no \"frozen\" tile", id));
+        }
+    }
+    table.link();
+    Ok(table)
+}
+
+fn load_level(level: &LevelJson) -> Result<Level, LoadError> {
+    let layers = level
+        .layer_instances
+        .as_ref()
+        .ok_or_else(|| invalid!("level {} is saved externally", level.identifier))?;
+    let layer = |identifier: &str| {
+        let layer = layers
+            .iter()
+            .find(|layer| layer.identifier == identifier)
+            .ok_or_else(|| invalid!("level {} has no {} layer", level.identifier, identifier))?;
+        if layer.grid_size != 16 {
+            return Err(invalid!(
+                "layer {} of level {} has grid size {}, expected 16",
+                identifier,
+                level.identifier,
+                layer.grid_size
+            ));
+        }
+        Ok(layer)
+    };
+
+    let bg_color = u32::from_str_radix(level.bg_color.trim_start_matches('#'), 16)
+        .map_err(|_| invalid!("invalid background color {:?}", level.bg_color))?;
+
+    let foreground = layer("Foreground")?;
+    let entities = layer("Entities")?;
+    let ambient_decorations = layer("AmbientDecorations")?;
+    let solid = layer("Solid")?;
+    let background = layer("Background")?;
+    Ok(Level {
+        bg_color: <Color as ColorImpl>::from_hex(bg_color << 8 | 0xFF),
+        pixel_size: UVec2::new(level.px_wid, level.px_hei),
+        world_depth: level.world_depth,
+        world_x: level.world_x,
+        world_y: level.world_y,
+        foreground: Foreground {
+            size: layer_size(foreground),
+            tiles: load_tiles(foreground)?,
+        },
+        entities: world::Entities {
+            size: layer_size(entities),
+            entities: entities
+                .entity_instances
+                .iter()
+                .map(load_entity)
+                .collect::<Result<_, _>>()?,
+        },
+        ambient_decorations: AmbientDecorations {
+            size: layer_size(ambient_decorations),
+            tiles: load_tiles(ambient_decorations)?,
+        },
+        solid: Solid {
+            size: layer_size(solid),
+            tiles: solid
+                .int_grid_csv
+                .iter()
+                .map(|value| match value {
+                    0 => Ok(SolidTile::Empty),
+                    1 => Ok(SolidTile::Ground),
+                    2 => Ok(SolidTile::Lamp),
+                    _ => Err(invalid!("unknown Solid value {}", value)),
+                })
+                .collect::<Result<_, _>>()?,
+            auto_tiles: {
+                let mut auto_tiles = vec![Vec::new(); (solid.c_wid * solid.c_hei) as usize];
+                for tile in &solid.auto_layer_tiles {
+                    let cell = tile_cell(solid, tile)?;
+                    auto_tiles[cell].push(load_tile(solid, tile)?);
+                }
+                auto_tiles
+            },
+        },
+        background: Background {
+            size: layer_size(background),
+            tiles: load_tiles(background)?,
+        },
+    })
+}
+
+fn layer_size(layer: &LayerJson) -> UVec2 {
+    UVec2::new(layer.c_wid, layer.c_hei)
+}
+
+fn tile_cell(layer: &LayerJson, tile: &TileJson) -> Result<usize, LoadError> {
+    tile.d
+        .last()
+        .copied()
+        .filter(|cell| *cell < (layer.c_wid * layer.c_hei) as usize)
+        .ok_or_else(|| invalid!("tile outside of layer {}", layer.identifier))
+}
+
+fn load_tile(layer: &LayerJson, tile: &TileJson) -> Result<Tile, LoadError> {
+    let flip = match tile.f {
+        0 => FlipMode::None,
+        1 => FlipMode::Horizontal,
+        2 => FlipMode::Vertical,
+        3 => FlipMode::Both,
+        _ => {
+            return Err(invalid!(
+                "invalid flip bits {} in {}",
+                tile.f,
+                layer.identifier
+            ))
+        }
+    };
+    Ok(Tile::new(
+        UVec2::new(tile.src[0] / layer.grid_size, tile.src[1] / layer.grid_size),
+        flip,
+    ))
+}
+
+fn load_tiles(layer: &LayerJson) -> Result<Vec<Option<Tile>>, LoadError> {
+    let mut tiles = vec![None; (layer.c_wid * layer.c_hei) as usize];
+    for tile in &layer.grid_tiles {
+        tiles[tile_cell(layer, tile)?] = Some(load_tile(layer, tile)?);
+    }
+    Ok(tiles)
+}
+
+fn load_entity(entity: &EntityJson) -> Result<EntityObject, LoadError> {
+    let field = |identifier: &str| {
+        entity
+            .field_instances
+            .iter()
+            .find(|field| field.identifier == identifier)
+            .map(|field| &field.value)
+            .ok_or_else(|| invalid!("{} has no field {}", entity.identifier, identifier))
+    };
+    let point = |identifier: &str| -> Result<UVec2, LoadError> {
+        let point = PointJson::deserialize(field(identifier)?)?;
+        Ok(UVec2::new(point.cx, point.cy))
+    };
+    let float = |identifier: &str| -> Result<f32, LoadError> {
+        Ok(field(identifier)?.as_f64().unwrap_or_default() as f32)
+    };
+
+    let kind = match entity.identifier.as_str() {
+        "Platform" => Entity::Platform(Platform {
+            condition: field("condition")?.as_str().unwrap_or_default().to_owned(),
+            point_true: point("point_true")?,
+            point_false: point("point_false")?,
+        }),
+        "PlayerStartPosition" => Entity::PlayerStartPosition(PlayerStartPosition),
+        "EndOfTheLevel" => Entity::EndOfTheLevel(EndOfTheLevel {
+            particle_timer: float("particle_timer")?,
+        }),
+        "Void" => Entity::Void(Void {
+            particle_timer: float("particle_timer")?,
+        }),
+        _ => return Err(invalid!("unknown entity {}", entity.identifier)),
+    };
+    Ok(EntityObject::new(
+        kind,
+        Vec2::new(entity.px[0], entity.px[1]),
+        UVec2::new(entity.width, entity.height),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_generated_world() {
+        let (world, tiles, rules) = load_file("src/world.ldtk").unwrap();
+        let generated = World::load();
+        assert_eq!(world.identifiers, generated.identifiers);
+        assert_eq!(world.len(), generated.len());
+        for (loaded, generated) in world.levels.iter().zip(&generated.levels) {
+            assert_eq!(format!("{:?}", loaded), format!("{:?}", generated));
+        }
+        assert_eq!(rules.levels.len(), world.len());
+        // The built-in world is played without its project, so it has to match the defaults
+        assert!(rules
+            .levels
+            .iter()
+            .all(|level| *level == LevelRules::default()));
+        assert!(!tiles.doors(&world[2]).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_projects() {
+        assert!(matches!(load("{"), Err(LoadError::Json(_))));
+    }
+}
@@ -0,0 +1,87 @@
+//! Hand-written list of levels built on top of the generated [`world`] types,
+//! so `src/world.rs` stays plain codegen output
+use super::*;
+use world::Level;
+
+/// Identifiers of the levels in the generated [`world::World`], in order.
+/// Keep in sync with `src/world.ldtk` like [`tiles::TileTable::builtin`]
+const BUILTIN_IDENTIFIERS: &[&str] = &["Level_0", "Level_1", "Level_2"];
+
+/// Any number of levels, accessible by index or by LDtk identifier
+#[derive(Clone, Debug)]
+pub struct World {
+    pub levels: Vec<Level>,
+    /// LDtk identifier of each level, same order as `levels`
+    pub identifiers: Vec<String>,
+}
+
+impl World {
+    /// Levels baked into the binary by the codegen
+    pub fn load() -> Self {
+        let generated = world::World::load();
+        let levels: Vec<_> = (0..)
+            .map_while(|index| generated.get(index).cloned())
+            .collect();
+        let identifiers = (0..levels.len())
+            .map(|index| match BUILTIN_IDENTIFIERS.get(index) {
+                Some(identifier) => identifier.to_string(),
+                None => format!("Level_{}", index),
+            })
+            .collect();
+        Self {
+            levels,
+            identifiers,
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Level> {
+        self.levels.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Level> {
+        self.levels.get_mut(index)
+    }
+
+    /// Identifier of the level at `index`, empty if there is no such level
+    pub fn identifier(&self, index: usize) -> &str {
+        self.identifiers.get(index).map_or("", String::as_str)
+    }
+
+    /// Index of the level with this identifier, ignoring case
+    pub fn index_of(&self, identifier: &str) -> Option<usize> {
+        self.identifiers
+            .iter()
+            .position(|other| other.eq_ignore_ascii_case(identifier))
+    }
+
+    pub fn find(&self, identifier: &str) -> Option<&Level> {
+        self.index_of(identifier).and_then(|index| self.get(index))
+    }
+
+    pub fn find_mut(&mut self, identifier: &str) -> Option<&mut Level> {
+        self.index_of(identifier)
+            .and_then(move |index| self.get_mut(index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+}
+
+impl std::ops::Index<usize> for World {
+    type Output = Level;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.levels[index]
+    }
+}
+
+impl std::ops::IndexMut<usize> for World {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.levels[index]
+    }
+}
@@ -5,6 +5,7 @@ use std::fmt::Debug;
 
 use assets::Assets;
 use controls::Controls;
+use game::Game;
 use player::Player;
 use rand::Rng;
 use speedy2d::color::Color;
@@ -16,8 +17,17 @@ use watch::{interpreter, Watch};
 use world::traits::*;
 
 pub mod assets;
+pub mod audio;
+pub mod collision;
 pub mod controls;
+pub mod game;
+pub mod ldtk;
+pub mod levels;
 pub mod player;
+pub mod replay;
+pub mod rules;
+pub mod save;
+pub mod tiles;
 pub mod watch;
 pub mod world;
 
@@ -28,7 +38,55 @@ fn main() {
         std::panic::set_hook(Box::new(console_error_panic_hook::hook));
     }
 
-    let handler = GarbageCollector3::new();
+    #[cfg(not(target_arch = "wasm32"))]
+    let handler = {
+        let args = Args::parse();
+        let builtin = || {
+            (
+                levels::World::load(),
+                tiles::TileTable::builtin(),
+                rules::Rules::default(),
+            )
+        };
+        let (world, tiles, rules) = match &args.world {
+            Some(path) => ldtk::load_file(path).unwrap_or_else(|err| {
+                eprintln!("{}: {}, falling back to built-in levels", path, err);
+                builtin()
+            }),
+            None => builtin(),
+        };
+        if args.validate {
+            let errors = watch::Interpreter::default().check_platforms(&world);
+            for error in &errors {
+                eprintln!("{}", error);
+            }
+            std::process::exit(if errors.is_empty() { 0 } else { 1 });
+        }
+        if let Some(path) = &args.replay {
+            let replay = replay::Replay::read(path).unwrap_or_else(|err| {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            });
+            if args.headless {
+                let mut game = replay.start(world, tiles, rules);
+                replay.play(&mut game, args.output().as_mut());
+                println!("finished: {}, timer: {:?}", game.finished, game.timer);
+                return;
+            }
+            GarbageCollector3::replaying(world, tiles, rules, replay)
+        } else if let Some(path) = &args.record {
+            GarbageCollector3::recording(world, tiles, rules, path)
+        } else {
+            GarbageCollector3::new(world, tiles, rules)
+        }
+        .with_output(args.output())
+    };
+    #[cfg(target_arch = "wasm32")]
+    let handler = GarbageCollector3::new(
+        levels::World::load(),
+        tiles::TileTable::builtin(),
+        rules::Rules::default(),
+    );
 
     #[cfg(target_arch = "wasm32")]
     speedy2d::WebCanvas::new_for_id("canvas", handler).unwrap();
@@ -39,347 +97,244 @@ fn main() {
     }
 }
 
+/// Command line arguments: `[world.ldtk] [--validate] [--record <path>] [--replay <path> [--headless]] [--wav <path>]`
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct Args {
+    world: Option<String>,
+    /// Check the world for errors and exit
+    validate: bool,
+    record: Option<String>,
+    replay: Option<String>,
+    headless: bool,
+    /// Write audio to a WAV file instead of playing it
+    wav: Option<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Args {
+    fn parse() -> Self {
+        let mut args = Self::default();
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--validate" => args.validate = true,
+                "--record" => args.record = iter.next(),
+                "--replay" => args.replay = iter.next(),
+                "--headless" => args.headless = true,
+                "--wav" => args.wav = iter.next(),
+                _ => args.world = Some(arg),
+            }
+        }
+        args
+    }
+
+    /// Where audio goes. Without a sound card, or when built without the `audio-device` feature, it's discarded
+    fn output(&self) -> Box<dyn audio::Output> {
+        if let Some(path) = &self.wav {
+            match audio::output::WavOutput::create(path) {
+                Ok(output) => return Box::new(output),
+                Err(err) => log_error(&format!("{}: {}", path, err)),
+            }
+        } else if !self.headless {
+            #[cfg(feature = "audio-device")]
+            match audio::output::DeviceOutput::new() {
+                Ok(output) => return Box::new(output),
+                Err(err) => log_error(&err.to_string()),
+            }
+        }
+        Box::new(audio::output::NullOutput)
+    }
+}
+
 struct GarbageCollector3 {
     stopwatch: speedy2d::time::Stopwatch,
     assets: Option<Assets>,
-    camera: Vec2,
     controls: Controls,
+    /// World as it was loaded, to restore saves on top of
+    world: levels::World,
+    tiles: tiles::TileTable,
+    rules: rules::Rules,
+    game: Game,
+    /// Owned here, so it keeps playing when a save is loaded
+    output: Box<dyn audio::Output>,
+    /// Whether [`GarbageCollector3::start_audio`] was called
+    #[cfg(target_arch = "wasm32")]
+    audio_started: bool,
 
-    level_index: usize,
-    introduced: bool,
-    dialogue: &'static [&'static str],
-
-    world: world::World,
-    player: Player,
-    watch: Watch,
-
-    particles: Vec<Particle>,
-    weather_particle_timer: f32,
-
-    timer: Option<f32>,
-    finished: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    recorder: Option<replay::Recorder>,
+    /// Replay being played and the index of the next frame
+    replay: Option<(replay::Replay, usize)>,
 }
 
 impl GarbageCollector3 {
-    fn new() -> Self {
-        let world = world::World::load();
-        let player = Player::new(get_player_start_position(&world.level_0.entities));
+    fn new(world: levels::World, tiles: tiles::TileTable, rules: rules::Rules) -> Self {
+        let game = Game::new(world.clone(), Vec2::new(854.0, 480.0))
+            .with_tiles(tiles)
+            .with_rules(rules);
+        let mut handler = Self::without_save(world, game);
+        handler.load();
+        handler
+    }
+
+    fn without_save(world: levels::World, game: Game) -> Self {
         Self {
+            tiles: game.tiles.clone(),
+            rules: game.rules.clone(),
             stopwatch: speedy2d::time::Stopwatch::new().unwrap(),
             assets: None,
-            camera: Vec2::ZERO,
             controls: Controls::default(),
-
-            level_index: 0,
-            introduced: false,
-            dialogue: &[],
-
             world,
-            player,
-            watch: Watch::default(),
-            particles: Vec::new(),
-            weather_particle_timer: 0.0,
+            game,
+            output: Box::new(audio::output::NullOutput),
+            #[cfg(target_arch = "wasm32")]
+            audio_started: false,
+
+            #[cfg(not(target_arch = "wasm32"))]
+            recorder: None,
+            replay: None,
+        }
+    }
 
-            timer: None,
-            finished: false,
+    /// Start a new game, recording it. Saves are not loaded, so the replay starts from the beginning
+    #[cfg(not(target_arch = "wasm32"))]
+    fn recording(
+        world: levels::World,
+        tiles: tiles::TileTable,
+        rules: rules::Rules,
+        path: &str,
+    ) -> Self {
+        let game = Game::new(world.clone(), Vec2::new(854.0, 480.0))
+            .with_tiles(tiles)
+            .with_rules(rules);
+        let mut handler = Self::without_save(world, game);
+        match replay::Recorder::create(path, handler.game.seed) {
+            Ok(recorder) => handler.recorder = Some(recorder),
+            Err(err) => log_error(&format!("{}: {}", path, err)),
         }
+        handler
     }
-}
 
-impl WindowHandler for GarbageCollector3 {
-    fn on_draw(&mut self, helper: &mut WindowHelper, graphics: &mut Graphics2D) {
-        let assets: &Assets = self.assets.get_or_insert_with(|| {
-            let assets = Assets::load(graphics);
-            self.player.size = UVec2::new(
-                assets.player.image.size().x / self.player.frame_count,
-                assets.player.image.size().y,
-            );
-            assets
-        });
-        let delta_time = self.stopwatch.secs_elapsed() as f32;
-        self.stopwatch = speedy2d::time::Stopwatch::new().unwrap();
+    fn replaying(
+        world: levels::World,
+        tiles: tiles::TileTable,
+        rules: rules::Rules,
+        replay: replay::Replay,
+    ) -> Self {
+        let game = replay.start(world.clone(), tiles, rules);
+        let mut handler = Self::without_save(world, game);
+        handler.replay = Some((replay, 0));
+        handler
+    }
 
-        if let Some(timer) = &mut self.timer {
-            if !self.finished {
-                *timer += delta_time;
-            }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn with_output(mut self, output: Box<dyn audio::Output>) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Browsers only let audio start from user input, so on web the output is made on the first key or click
+    #[cfg(target_arch = "wasm32")]
+    fn start_audio(&mut self) {
+        if self.audio_started {
+            return;
+        }
+        self.audio_started = true;
+        #[cfg(feature = "audio-device")]
+        match audio::output::DeviceOutput::new() {
+            Ok(output) => self.output = Box::new(output),
+            Err(err) => log_error(&err.to_string()),
         }
+    }
 
-        let level = &self.world[self.level_index];
-
-        let scale = helper.get_size_pixels().y as f32 / 256.0;
-        let screen_size = helper.get_size_pixels().into_f32() / scale;
-        if !self.watch.open && self.dialogue.is_empty() {
-            self.player.update(delta_time, level, &self.controls);
-            for entity in level.entities.entities() {
-                if self.player.overlaps(entity) {
-                    match entity.entity {
-                        world::Entity::EndOfTheLevel(_) => {
-                            self.level_index += 1;
-                            self.player.position =
-                                get_player_start_position(&self.world[self.level_index].entities);
-                            self.camera = self.player.position + self.player.size.into_f32() / 2.0
-                                - screen_size / 2.0;
-                        }
-
-                        world::Entity::Void(_) => {
-                            if self.level_index == 0 {
-                                if !self.introduced {
-                                    self.dialogue = &[
-                                    "Hey!",
-                                    "I'm Void, and you probably have heard of me.",
-                                    "So, I just finished designing this watch...",
-                                    "It's not your usual fitness bracelet. It's something more!",
-                                    "And I want you to test it...",
-                                    "Can you just *run* through this obstacle course I made for you as fast as possible?",
-                                    "You might need to *write* some code to unleash bracelet's full potential...",
-                                    "Your time starts... Now!",
-                                ];
-                                    self.introduced = true;
-                                }
-                            } else {
-                                self.finished = true;
-                                self.dialogue = &["You did it! It only took you $TIME"];
-                            }
-                        }
-                        _ => (),
-                    }
+    /// Step the game with live, recorded or replayed input. Returns the time that was stepped
+    fn step(&mut self, delta_time: f32) -> f32 {
+        if let Some((replay, index)) = &mut self.replay {
+            match replay.frames.get(*index) {
+                Some(frame) => {
+                    *index += 1;
+                    replay::Replay::step(&mut self.game, frame);
+                    return frame.delta_time;
+                }
+                None => {
+                    println!(
+                        "replay finished: {}, timer: {:?}",
+                        self.game.finished, self.game.timer
+                    );
+                    self.replay = None;
                 }
             }
         }
 
-        for particle in &mut self.particles {
-            particle.update(level, delta_time);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(err) = recorder.step(&mut self.game, delta_time, &self.controls) {
+                log_error(&err.to_string());
+                self.recorder = None;
+            }
+            return delta_time;
         }
-        self.particles.retain(|particle| particle.lifetime > 0.0);
-
-        self.camera += ((self.player.position + self.player.size.into_f32() / 2.0
-            - screen_size / 2.0)
-            - self.camera)
-            * (1.0 - 0.05_f32.powf(delta_time));
-        self.camera.x = self
-            .camera
-            .x
-            .clamp(0.0, level.pixel_size.x as f32 - screen_size.x);
-        self.camera.y = self
-            .camera
-            .y
-            .clamp(0.0, level.pixel_size.y as f32 - screen_size.y);
-        let mut camera = Camera {
-            graphics,
-            scale,
-            position: self.camera,
-        };
 
-        {
-            let weather = *watch::interpreter::pywatch::WEATHER.lock().unwrap();
-            camera.graphics.clear_screen(match weather {
-                interpreter::pywatch::Weather::Sunny => level.bg_color,
-                interpreter::pywatch::Weather::Rainy => Color::from_hex_rgb(0x9F9F9F),
-                interpreter::pywatch::Weather::Snowy => Color::from_hex_rgb(0xDADADA),
-            });
+        self.game.step(delta_time, &self.controls);
+        delta_time
+    }
 
-            let (pps, particle_color) = match weather {
-                interpreter::pywatch::Weather::Rainy => (
-                    0.1 * level.pixel_size.x as f32,
-                    Color::from_hex_rgb(0x00057F),
-                ),
-                interpreter::pywatch::Weather::Snowy => (
-                    0.1 * level.pixel_size.x as f32,
-                    Color::from_hex_rgb(0xFFFFFF),
-                ),
-                _ => (0.0, Color::MAGENTA),
-            };
-            self.weather_particle_timer += delta_time;
-            while self.weather_particle_timer > 1.0 / pps {
-                self.weather_particle_timer -= 1.0 / pps;
-                self.particles.push(Particle::new(
-                    Vec2::new(
-                        rand::thread_rng().gen_range(0.0..level.pixel_size.x as f32),
-                        rand::thread_rng().gen_range(-20.0..20.0_f32),
-                    ),
-                    Vec2::ZERO,
-                    ParticleVisual::Color(particle_color, Vec2::new(1.0, 2.5), false),
-                    true,
-                ));
-            }
-            if pps == 0.0 {
-                self.weather_particle_timer = 0.0;
-            }
+    /// Whether a replay is being recorded or played. Loading a save would make it diverge
+    fn is_replay(&self) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.recorder.is_some() {
+            return true;
         }
+        self.replay.is_some()
+    }
 
-        camera.draw_tiles(screen_size, assets, &level.background);
-        camera.draw_autotile(screen_size, assets, &level.solid);
-        camera.draw_tiles(screen_size, assets, &level.ambient_decorations);
-        let level = &mut self.world[self.level_index];
-        for entity in level.entities.entities_mut() {
-            match &mut entity.entity {
-                world::Entity::EndOfTheLevel(eol) => {
-                    let pps = 100.0;
-                    eol.particle_timer += delta_time;
-                    while eol.particle_timer > 1.0 / pps {
-                        eol.particle_timer -= 1.0 / pps;
-                        let direction =
-                            rand::thread_rng().gen_range(0.0..std::f32::consts::PI * 2.0);
-                        let velocity = direction.sin_cos();
-                        let velocity = Vec2::new(velocity.0, velocity.1)
-                            * rand::thread_rng().gen_range(0.0..100.0);
-                        self.particles.push(Particle::new(
-                            entity.position,
-                            velocity,
-                            ParticleVisual::Color(Color::WHITE, Vec2::new(2.0, 2.0), true),
-                            false,
-                        ));
-                    }
-                }
-                world::Entity::Void(void) => {
-                    let pps = 100.0;
-                    void.particle_timer += delta_time;
-                    while void.particle_timer > 1.0 / pps {
-                        void.particle_timer -= 1.0 / pps;
-                        let direction =
-                            rand::thread_rng().gen_range(0.0..std::f32::consts::PI * 2.0);
-                        let velocity = direction.sin_cos();
-                        let velocity = Vec2::new(velocity.0, velocity.1)
-                            * rand::thread_rng().gen_range(0.0..100.0);
-                        self.particles.push(Particle::new(
-                            entity.position,
-                            velocity,
-                            ParticleVisual::Texture(rand::thread_rng().gen_range(0..=1)),
-                            true,
-                        ));
-                    }
-                }
-                world::Entity::Platform(platform) => {
-                    let point_true = platform.point_true.into_f32() + Vec2::new(0.5, 0.5);
-                    let point_true = (point_true * world::Entities::GRID_SIZE as f32
-                        - camera.position)
-                        * camera.scale;
-                    let point_false = platform.point_false.into_f32() + Vec2::new(0.5, 0.5);
-                    let point_false = (point_false * world::Entities::GRID_SIZE as f32
-                        - camera.position)
-                        * camera.scale;
-                    let condition = platform.condition.clone();
-                    camera.graphics.draw_line(
-                        point_true,
-                        point_false,
-                        camera.scale,
-                        Color::from_hex_rgb(0x52333f),
-                    );
-                    camera
-                        .graphics
-                        .draw_circle(point_true, 2.0 * camera.scale, Color::GREEN);
-                    camera
-                        .graphics
-                        .draw_circle(point_false, 2.0 * camera.scale, Color::RED);
-                    camera.draw_tile(
-                        entity.top_left(),
-                        false,
-                        UVec2::new(5, 0),
-                        UVec2::new(32, 16),
-                        &assets.tileset,
-                        false,
-                        false,
-                    );
-                    let text = assets.font.layout_text(
-                        &condition,
-                        10.0 * camera.scale,
-                        speedy2d::font::TextOptions::new(),
-                    );
-                    let size = text.size();
-                    camera.graphics.draw_text(
-                        point_true - Vec2::new(size.x * 0.5, size.y),
-                        Color::BLACK,
-                        &text,
-                    );
-                }
-                _ => (),
-            }
+    fn save(&self) {
+        if let Err(err) = self.game.save().write() {
+            log_error(&err.to_string());
         }
-        self.player.draw(&mut camera, assets, self.introduced);
-        camera.draw_tiles(screen_size, assets, &level.foreground);
-        for particle in &self.particles {
-            particle.draw(&mut camera, assets);
-        }
-        if self.introduced {
-            self.watch.draw(
-                helper,
-                delta_time,
-                &self.controls,
-                &mut camera,
-                assets,
-                level,
-                &self.player,
-            );
+    }
+
+    fn load(&mut self) {
+        let save = match save::Save::read() {
+            Ok(Some(save)) => save,
+            Ok(None) => return,
+            Err(err) => return log_error(&err.to_string()),
+        };
+        match Game::from_save(
+            self.world.clone(),
+            self.tiles.clone(),
+            self.rules.clone(),
+            self.game.viewport,
+            save,
+        ) {
+            Ok(mut game) => {
+                game.audio = std::mem::take(&mut self.game.audio);
+                self.game = game;
+            }
+            Err(err) => log_error(&err.to_string()),
         }
-        if let Some(line) = self.dialogue.first() {
-            let mut position = helper.get_size_pixels().into_f32();
-            position.x *= 0.5;
-            position.y *= 0.5;
-            let text = assets.font.layout_text(
-                &line.replace(
-                    "$TIME",
-                    &self.timer.map_or("???".to_owned(), |time| {
-                        format!(
-                            "{:02}:{:02}:{:01.2}",
-                            (time / 60.0 / 60.0) as i32,
-                            (time / 60.0) as i32 % 60,
-                            time % 60.0
-                        )
-                    }),
-                ),
-                12.0 * camera.scale,
-                speedy2d::font::TextOptions::new()
-                    .with_wrap_to_width(80.0 * camera.scale, speedy2d::font::TextAlignment::Left),
-            );
-            let size = text.size();
-            let border = 5.0;
-            let mut rect_size = size;
-            rect_size.x = rect_size.x.max(80.0 * camera.scale);
-            rect_size.y = rect_size.y.max(60.0 * camera.scale);
-            // * Outer border
-            let mut rect = speedy2d::shape::RoundRect::new(
-                position - rect_size / 2.0 - Vec2::new(1.0, 1.0) * 10.0 * camera.scale,
-                position + rect_size / 2.0 + Vec2::new(1.0, 1.0) * 10.0 * camera.scale,
-                10.0 * camera.scale,
-            );
-            camera
-                .graphics
-                .draw_rounded_rectangle(&rect, Color::from_hex_rgb(0xdfe0e8));
-            // * Inner border
-            rect = speedy2d::shape::RoundRect::new(
-                rect.top_left() + Vec2::new(1.0, 1.0) * border / 2.0 * camera.scale,
-                rect.bottom_right() - Vec2::new(1.0, 1.0) * border / 2.0 * camera.scale,
-                rect.radius() - border / 2.0 * camera.scale,
-            );
-            camera
-                .graphics
-                .draw_rounded_rectangle(&rect, Color::from_hex_rgb(0x686f99));
-            // * Inside
-            rect = speedy2d::shape::RoundRect::new(
-                rect.top_left() + Vec2::new(1.0, 1.0) * border / 2.0 * camera.scale,
-                rect.bottom_right() - Vec2::new(1.0, 1.0) * border / 2.0 * camera.scale,
-                rect.radius() - border / 2.0 * camera.scale,
-            );
-            camera
-                .graphics
-                .draw_rounded_rectangle(&rect, Color::from_hex_rgb(0x3d2936));
-
-            use speedy2d::numeric::RoundFloat;
-            camera
-                .graphics
-                .draw_text((position - size / 2.0).round(), Color::WHITE, &text);
-            if self.controls.dialogue_next() {
-                self.dialogue = &self.dialogue[1..];
-                if self.dialogue.is_empty() {
-                    if self.level_index == 0 {
-                        self.timer = Some(0.0);
-                    }
-                    level
-                        .entities
-                        .entities_mut()
-                        .retain(|entity| !matches!(entity.entity, world::Entity::Void(_)));
-                }
+    }
+}
+
+impl WindowHandler for GarbageCollector3 {
+    fn on_draw(&mut self, helper: &mut WindowHelper, graphics: &mut Graphics2D) {
+        let delta_time = self.stopwatch.secs_elapsed() as f32;
+        self.stopwatch = speedy2d::time::Stopwatch::new().unwrap();
+
+        self.game.viewport = helper.get_size_pixels().into_f32();
+        let progress = (self.game.level_index, self.game.finished);
+        let delta_time = self.step(delta_time);
+        self.game.audio.mix(delta_time, self.output.as_mut());
+        let assets: &Assets = self.assets.get_or_insert_with(|| Assets::load(graphics));
+        self.game.draw(graphics, assets);
+        if !self.is_replay() {
+            if self.controls.quick_save() || progress != (self.game.level_index, self.game.finished)
+            {
+                self.save();
+            }
+            if self.controls.quick_load() {
+                self.load();
             }
         }
         self.controls.reset();
@@ -392,6 +347,8 @@ impl WindowHandler for GarbageCollector3 {
         virtual_key_code: Option<speedy2d::window::VirtualKeyCode>,
         _scancode: speedy2d::window::KeyScancode,
     ) {
+        #[cfg(target_arch = "wasm32")]
+        self.start_audio();
         if let Some(virtual_key_code) = virtual_key_code {
             self.controls.pressed.insert(virtual_key_code, true);
             self.controls.jpressed.insert(virtual_key_code, true);
@@ -414,7 +371,7 @@ impl WindowHandler for GarbageCollector3 {
         _helper: &mut WindowHelper<()>,
         state: speedy2d::window::ModifiersState,
     ) {
-        self.controls.mods = state.clone();
+        self.controls.mods = (&state).into();
     }
 
     fn on_mouse_move(&mut self, _helper: &mut WindowHelper<()>, position: Vec2) {
@@ -426,6 +383,8 @@ impl WindowHandler for GarbageCollector3 {
         _helper: &mut WindowHelper<()>,
         button: speedy2d::window::MouseButton,
     ) {
+        #[cfg(target_arch = "wasm32")]
+        self.start_audio();
         self.controls.mouse_buttons.insert(button, true);
     }
 
@@ -463,7 +422,7 @@ impl Particle {
         }
     }
 
-    pub fn update(&mut self, level: &world::Level, delta_time: f32) {
+    pub fn update(&mut self, level: &world::Level, tiles: &tiles::TileTable, delta_time: f32) {
         self.position += self.velocity * delta_time;
         if self.gravity {
             self.velocity.y += 18.0 * 32.0 * delta_time;
@@ -477,7 +436,7 @@ impl Particle {
             self.lifetime = -1.0
         }
         if let Some(tile) = level.background.get(tile_pos) {
-            if tile.position.x == 7 {
+            if tiles.get(tile.position).blocks_particles {
                 self.lifetime = -1.0;
             }
         }
@@ -557,16 +516,21 @@ impl Camera<'_> {
         );
     }
 
-    pub fn draw_autotile(&mut self, screen_size: Vec2, assets: &Assets, layer: &impl AutoLayer) {
-        let weather = *watch::interpreter::pywatch::WEATHER.lock().unwrap();
+    pub fn draw_autotile(
+        &mut self,
+        screen_size: Vec2,
+        assets: &Assets,
+        tiles: &tiles::TileTable,
+        weather: watch::interpreter::pywatch::Weather,
+        layer: &impl AutoLayer,
+    ) {
         let (tl, size) = self.view_rect(screen_size, layer.grid_size());
-        for (pos, tiles) in layer.autotile_rect(tl, size) {
-            for mut tile in tiles {
-                if tile.position.x <= 3
-                    && tile.position.y <= 1
-                    && weather == watch::interpreter::pywatch::Weather::Snowy
-                {
-                    tile.position.y += 2;
+        for (pos, autotiles) in layer.autotile_rect(tl, size) {
+            for mut tile in autotiles {
+                if weather == watch::interpreter::pywatch::Weather::Snowy {
+                    if let Some(frozen) = tiles.get(tile.position).frozen {
+                        tile.position = frozen;
+                    }
                 }
                 self.draw_tile(
                     Vec2::new(
@@ -618,6 +582,13 @@ impl Camera<'_> {
     }
 }
 
+pub fn log_error(message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    web_sys::console::error_1(&message.into());
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("{}", message);
+}
+
 pub fn get_player_start_position(entities: &world::Entities) -> Vec2 {
     for entity in entities.entities() {
         if matches!(entity.entity, world::Entity::PlayerStartPosition(_)) {
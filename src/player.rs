@@ -13,12 +13,21 @@ const FRAMES: &[(&str, u32)] = &[
     ("slide_end", 1),
 ];
 
+/// Physics runs at a fixed rate, so movement doesn't depend on the framerate
+pub const PHYSICS_STEP: f32 = 1.0 / 120.0;
+/// Longest frame that is simulated. Anything above (window drag, tab switch) is dropped
+const MAX_FRAME_TIME: f32 = 0.25;
+
 pub struct Player {
     pub frames: std::collections::HashMap<&'static str, std::ops::Range<u32>>,
     pub frame_count: u32,
 
     pub velocity: Vec2,
     pub position: Vec2,
+    /// Position before the last physics step, for interpolation
+    pub previous_position: Vec2,
+    /// Time not yet simulated
+    pub accumulator: f32,
     pub grounded: bool,
     pub slide_timeout: f32,
     pub slippery: bool,
@@ -30,6 +39,8 @@ pub struct Player {
     pub looped: bool,
 
     pub size: UVec2,
+    /// Sounds of the transitions since the last frame
+    pub effects: Vec<audio::Effect>,
 }
 
 impl Player {
@@ -40,12 +51,19 @@ impl Player {
             frames.insert(*name, frame_count..frame_count + length);
             frame_count += length;
         }
+        let (width, height) = image::io::Reader::new(std::io::Cursor::new(assets::PLAYER_IMAGE))
+            .with_guessed_format()
+            .unwrap()
+            .into_dimensions()
+            .unwrap();
         Self {
             frames,
             frame_count,
 
             velocity: Vec2::ZERO,
             position,
+            previous_position: position,
+            accumulator: 0.0,
             grounded: false,
             slide_timeout: 0.0,
             slippery: false,
@@ -56,11 +74,39 @@ impl Player {
             animation: "idle",
             looped: true,
 
-            size: UVec2::ZERO,
+            size: UVec2::new(width / frame_count, height),
+            effects: Vec::new(),
         }
     }
 
-    pub fn update(&mut self, delta_time: f32, level: &world::Level, controls: &Controls) {
+    /// Advance physics by [`PHYSICS_STEP`]s, carrying the rest of `delta_time` over to the next frame
+    pub fn update(
+        &mut self,
+        delta_time: f32,
+        collision: &collision::CollisionGrid,
+        controls: &Controls,
+    ) {
+        self.accumulator += delta_time.min(MAX_FRAME_TIME);
+        while self.accumulator >= PHYSICS_STEP {
+            self.accumulator -= PHYSICS_STEP;
+            self.previous_position = self.position;
+            self.step(PHYSICS_STEP, collision, controls);
+        }
+    }
+
+    /// Position between the last two physics steps, matching the time left in the accumulator
+    pub fn interpolated_position(&self) -> Vec2 {
+        let alpha = self.accumulator / PHYSICS_STEP;
+        self.previous_position + (self.position - self.previous_position) * alpha
+    }
+
+    /// Move without interpolating from the old position
+    pub fn teleport(&mut self, position: Vec2) {
+        self.position = position;
+        self.previous_position = position;
+    }
+
+    fn step(&mut self, delta_time: f32, collision: &collision::CollisionGrid, controls: &Controls) {
         let key_dir = controls.right() as i32 - controls.left() as i32;
         let target_velocity = key_dir as f32 * 196.0;
 
@@ -73,7 +119,7 @@ impl Player {
             if controls.slide() && self.velocity.x.abs() > 180.0 && self.slide_timeout <= 0.0 {
                 let anim = self.animation;
                 self.animation = "slide";
-                if self.collides(level) {
+                if self.collides(collision) {
                     self.animation = anim;
                 } else {
                     self.transition("slide_start");
@@ -84,7 +130,7 @@ impl Player {
             if !controls.slide() || !self.grounded || self.velocity.x.abs() < 128.0 {
                 let anim = self.animation;
                 self.animation = "idle";
-                if self.collides(level) {
+                if self.collides(collision) {
                     self.animation = anim;
                     self.velocity.x = self.velocity.x.signum() * 128.0;
                 } else {
@@ -127,9 +173,9 @@ impl Player {
             self.animation = "idle";
         }
         self.slippery = false;
-        self.move_in_steps(level, Vec2::new_x(motion.x));
-        self.move_in_steps(level, Vec2::new_y(motion.y));
-        while self.collides(level) {
+        self.move_and_collide(collision, Vec2::new_x(motion.x));
+        self.move_and_collide(collision, Vec2::new_y(motion.y));
+        while self.collides(collision) {
             self.position.y -= 0.1;
         }
 
@@ -185,50 +231,8 @@ impl Player {
         }
     }
 
-    pub fn collides(&mut self, level: &world::Level) -> bool {
-        let (tl, size) = self.tile_rect(level.solid.grid_size());
-        let br = tl + size.into_i32();
-        if tl.x < 0
-            || tl.y < 0
-            || br.x > level.solid.size.x as i32
-            || br.y > level.solid.size.y as i32
-        {
-            return true;
-        }
-        for (pos, tile) in level.solid.rect(tl, size) {
-            match tile {
-                Some(world::SolidTile::Ground) => return true,
-                Some(world::SolidTile::Lamp) => {
-                    if level.solid.get(pos - IVec2::new_y(1)) != Some(&world::SolidTile::Lamp) {
-                        return true;
-                    }
-                }
-                _ => (),
-            }
-        }
-        for tile in level
-            .background
-            .rect(tl, size)
-            .flat_map(|(_, tile)| tile)
-            .chain(level.foreground.rect(tl, size).flat_map(|(_, tile)| tile))
-        {
-            if tile.position == UVec2::new(7, 1) {
-                self.slippery = true;
-                return true;
-            }
-            let solids = [UVec2::new(7, 2), UVec2::new(7, 3)];
-            if solids.contains(&tile.position) {
-                return true;
-            }
-        }
-        for entity in level.entities.entities() {
-            if self.overlaps(entity) {
-                if let world::Entity::Platform(_) = &entity.entity {
-                    return true;
-                }
-            }
-        }
-        false
+    pub fn collides(&self, collision: &collision::CollisionGrid) -> bool {
+        collision.overlaps(self.rect())
     }
 
     pub fn transition(&mut self, animation: &'static str) {
@@ -236,6 +240,13 @@ impl Player {
             return;
         }
         self.animation = animation;
+        match animation {
+            "jump" => self.effects.push(audio::Effect::Jump),
+            "land" => self.effects.push(audio::Effect::Land),
+            "kick" => self.effects.push(audio::Effect::Kick),
+            "slide_start" => self.effects.push(audio::Effect::Slide),
+            _ => (),
+        }
         self.looped = !matches!(
             animation,
             "jump" | "land" | "kick" | "slide_start" | "slide_end"
@@ -243,41 +254,36 @@ impl Player {
         self.frame = 0.0;
     }
 
-    pub fn move_in_steps(&mut self, level: &world::Level, motion: Vec2) {
-        let mut distance = motion.magnitude();
+    /// Move until the first contact, which drives wall-slide, landing and slide-end transitions
+    pub fn move_and_collide(&mut self, collision: &collision::CollisionGrid, motion: Vec2) {
+        let distance = motion.magnitude();
         if distance == 0.0 {
             return;
         }
-        let mut step = 0.1;
-        let dir = motion / distance;
-        while distance > 0.0 {
-            if distance < step {
-                step = distance;
+        let Some(contact) = collision.sweep(self.rect(), motion) else {
+            self.position += motion;
+            return;
+        };
+        self.position += motion / distance * (distance * contact.time - collision::SKIN).max(0.0);
+
+        if contact.normal.x != 0.0 {
+            if self.last_grounded > 0.3 {
+                self.transition("wall_slide");
+            } else if self.animation == "slide" || self.animation == "slide_start" {
+                self.transition("slide_end");
             }
-            distance -= step;
-            self.position += dir * step;
-            if self.collides(level) {
-                self.position -= dir * step;
-                if motion.x != 0.0 {
-                    if self.last_grounded > 0.3 {
-                        self.transition("wall_slide");
-                    } else if self.animation == "slide" || self.animation == "slide_start" {
-                        self.transition("slide_end");
-                    }
-                }
-                if motion.y != 0.0 {
-                    self.velocity.y = 0.0;
-                }
-                if motion.y > 0.0 {
-                    self.grounded = true;
-                    if self.last_grounded > 0.1
-                        && self.animation != "slide"
-                        && self.animation != "slide_start"
-                    {
-                        self.transition("land");
-                    }
-                }
-                break;
+        }
+        if contact.normal.y != 0.0 {
+            self.velocity.y = 0.0;
+        }
+        if contact.normal.y < 0.0 {
+            self.grounded = true;
+            self.slippery = contact.slippery;
+            if self.last_grounded > 0.1
+                && self.animation != "slide"
+                && self.animation != "slide_start"
+            {
+                self.transition("land");
             }
         }
     }
@@ -300,23 +306,9 @@ impl Player {
         (tl, br)
     }
 
-    pub fn tile_rect(&self, grid_size: UVec2) -> (IVec2, UVec2) {
-        let (tl, br) = self.rect();
-        let tl = IVec2::new(
-            (tl.x / grid_size.x as f32).floor() as _,
-            (tl.y / grid_size.y as f32).floor() as _,
-        );
-        let br = IVec2::new(
-            (br.x / grid_size.x as f32).ceil() as _,
-            (br.y / grid_size.y as f32).ceil() as _,
-        );
-        let size = br - tl;
-        (tl, size.into_u32())
-    }
-
     pub fn draw(&self, camera: &mut Camera, assets: &Assets, has_watch: bool) {
         camera.draw_tile(
-            self.position,
+            self.interpolated_position(),
             false,
             UVec2::new_x(self.frame as u32 + self.frames[self.animation].start),
             self.size,
@@ -335,9 +327,9 @@ impl Player {
     }
 
     pub fn overlaps(&self, entity: &world::EntityObject) -> bool {
-        let(tl, br) = self.rect();
-        br.x as f32 > entity.top_left().x
-            && br.y as f32 > entity.top_left().y
+        let (tl, br) = self.rect();
+        br.x > entity.top_left().x
+            && br.y > entity.top_left().y
             && tl.x < entity.top_left().x + entity.size.x as f32
             && tl.y < entity.top_left().y + entity.size.y as f32
     }
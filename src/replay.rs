@@ -0,0 +1,316 @@
+//! Recording and replaying input. A replay is a JSON Lines file: a [`ReplayHeader`] followed
+//! by one [`ReplayFrame`] per frame, so it can be written as the game goes
+use super::*;
+use controls::{Modifiers, KEYS, MOUSE_BUTTONS};
+use serde::{Deserialize, Serialize};
+
+/// Bump when the format changes. Replays with a different version are rejected
+pub const REPLAY_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub seed: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub delta_time: f32,
+    pub viewport: [f32; 2],
+
+    pub pressed: Vec<String>,
+    pub jpressed: Vec<String>,
+    pub mods: Modifiers,
+    pub mouse_pos: [f32; 2],
+    pub mouse_buttons: Vec<String>,
+    pub typed_text: String,
+
+    /// Code ran with `watch.run` during this frame
+    pub runs: Vec<ReplayRun>,
+    /// What `watch.time(real=True)` returned during this frame
+    #[serde(default)]
+    pub real_time: f64,
+}
+
+/// Code ran with `watch.run`, replayed at the same place in the action queue
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReplayRun {
+    /// How many actions other than runs were processed before it in its frame
+    pub position: usize,
+    pub code: String,
+}
+
+impl ReplayFrame {
+    pub fn new(delta_time: f32, viewport: Vec2, controls: &Controls) -> Self {
+        fn names<T: Eq + std::hash::Hash>(
+            table: &[(T, &str)],
+            pressed: &std::collections::HashMap<T, bool>,
+        ) -> Vec<String> {
+            table
+                .iter()
+                .filter(|(key, _)| pressed.get(key).is_some_and(|pressed| *pressed))
+                .map(|(_, name)| (*name).to_owned())
+                .collect()
+        }
+
+        Self {
+            delta_time,
+            viewport: [viewport.x, viewport.y],
+
+            pressed: names(KEYS, &controls.pressed),
+            jpressed: names(KEYS, &controls.jpressed),
+            mods: controls.mods,
+            mouse_pos: [controls.mouse_pos.x, controls.mouse_pos.y],
+            mouse_buttons: names(MOUSE_BUTTONS, &controls.mouse_buttons),
+            typed_text: controls.typed_text.clone(),
+
+            runs: Vec::new(),
+            real_time: 0.0,
+        }
+    }
+
+    pub fn viewport(&self) -> Vec2 {
+        Vec2::new(self.viewport[0], self.viewport[1])
+    }
+
+    pub fn controls(&self) -> Controls {
+        fn keys<T: Copy + Eq + std::hash::Hash>(
+            table: &[(T, &str)],
+            names: &[String],
+        ) -> std::collections::HashMap<T, bool> {
+            table
+                .iter()
+                .filter(|(_, name)| names.iter().any(|pressed| pressed == name))
+                .map(|(key, _)| (*key, true))
+                .collect()
+        }
+
+        Controls {
+            pressed: keys(KEYS, &self.pressed),
+            jpressed: keys(KEYS, &self.jpressed),
+            mods: self.mods,
+            mouse_pos: Vec2::new(self.mouse_pos[0], self.mouse_pos[1]),
+            mouse_buttons: keys(MOUSE_BUTTONS, &self.mouse_buttons),
+            typed_text: self.typed_text.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Version(u32),
+    Empty,
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to access replay: {}", err),
+            Self::Json(err) => write!(f, "failed to parse replay: {}", err),
+            Self::Version(version) => write!(
+                f,
+                "replay version {} is not supported (expected {})",
+                version, REPLAY_VERSION
+            ),
+            Self::Empty => write!(f, "replay is empty"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ReplayError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+    pub fn parse(source: &str) -> Result<Self, ReplayError> {
+        let mut lines = source.lines().filter(|line| !line.trim().is_empty());
+        let header: ReplayHeader = serde_json::from_str(lines.next().ok_or(ReplayError::Empty)?)?;
+        if header.version != REPLAY_VERSION {
+            return Err(ReplayError::Version(header.version));
+        }
+        Ok(Self {
+            header,
+            frames: lines.map(serde_json::from_str).collect::<Result<_, _>>()?,
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read(path: impl AsRef<std::path::Path>) -> Result<Self, ReplayError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Start a game to play this replay in
+    pub fn start(
+        &self,
+        world: levels::World,
+        tiles: tiles::TileTable,
+        rules: rules::Rules,
+    ) -> Game {
+        let viewport = self
+            .frames
+            .first()
+            .map_or(Vec2::new(854.0, 480.0), ReplayFrame::viewport);
+        Game::new(world, viewport)
+            .with_tiles(tiles)
+            .with_rules(rules)
+            .with_seed(self.header.seed)
+    }
+
+    /// Play the whole replay without rendering, mixing its audio into `output`
+    pub fn play(&self, game: &mut Game, output: &mut dyn audio::Output) {
+        for frame in &self.frames {
+            Self::step(game, frame);
+            game.audio.mix(frame.delta_time, output);
+        }
+    }
+
+    /// Step the game with recorded input
+    pub fn step(game: &mut Game, frame: &ReplayFrame) {
+        game.viewport = frame.viewport();
+        game.watch.interpreter.replayed_runs = Some(frame.runs.clone());
+        game.watch.interpreter.replayed_real_time = Some(frame.real_time);
+        game.step(frame.delta_time, &frame.controls());
+        game.watch.interpreter.replayed_runs = None;
+        game.watch.interpreter.replayed_real_time = None;
+    }
+}
+
+/// Writes a replay as the game goes
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Recorder {
+    file: std::io::BufWriter<std::fs::File>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Recorder {
+    pub fn create(path: impl AsRef<std::path::Path>, seed: u64) -> Result<Self, ReplayError> {
+        let mut recorder = Self {
+            file: std::io::BufWriter::new(std::fs::File::create(path)?),
+        };
+        recorder.write_line(&ReplayHeader {
+            version: REPLAY_VERSION,
+            seed,
+        })?;
+        Ok(recorder)
+    }
+
+    /// Step the game, recording the input
+    pub fn step(
+        &mut self,
+        game: &mut Game,
+        delta_time: f32,
+        controls: &Controls,
+    ) -> Result<(), ReplayError> {
+        let mut frame = ReplayFrame::new(delta_time, game.viewport, controls);
+        game.step(delta_time, controls);
+        frame.runs = std::mem::take(&mut game.watch.interpreter.recorded_runs);
+        frame.real_time = game.watch.interpreter.context.clock.lock().unwrap().real;
+        self.write_line(&frame)
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) -> Result<(), ReplayError> {
+        use std::io::Write;
+        serde_json::to_writer(&mut self.file, value)?;
+        writeln!(self.file)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use speedy2d::window::VirtualKeyCode;
+
+    #[test]
+    fn replays_to_the_same_state() {
+        let path = std::env::temp_dir().join(format!(
+            "garbage_collector3-test-{}.replay",
+            std::process::id()
+        ));
+        let start = || {
+            (
+                levels::World::load(),
+                tiles::TileTable::builtin(),
+                rules::Rules::default(),
+            )
+        };
+
+        let (world, tiles, rules) = start();
+        let mut game = Game::new(world, Vec2::new(854.0, 480.0))
+            .with_tiles(tiles)
+            .with_rules(rules)
+            .with_seed(1234);
+        game.introduced = true;
+        let spawn = game.player.position;
+        let mut recorder = Recorder::create(&path, game.seed).unwrap();
+        let mut controls = Controls::default();
+        for frame in 0..180 {
+            controls.pressed.insert(VirtualKeyCode::Right, true);
+            controls
+                .pressed
+                .insert(VirtualKeyCode::Space, frame % 60 < 20);
+            if frame == 30 {
+                // The runs it queues go around an app being added, and have to stay in that order
+                let code =
+                    "import random, sys\nroll = random.random()\nstarted = watch.time(real=True)\n\
+                    watch.run(\"installed = 'recorded' in sys.modules\")\n\
+                    watch.add_app('recorded', 'value = 1\\ndef frame(frame):\\n    return False')\n\
+                    watch.run('import recorded\\nvalue = recorded.value')";
+                game.watch
+                    .interpreter
+                    .context
+                    .queue(watch::interpreter::pywatch::Action::Run(code.to_owned()));
+            }
+            recorder.step(&mut game, 1.0 / 60.0, &controls).unwrap();
+        }
+        drop(recorder);
+        let recorded = game.save().to_json();
+        let position = game.player.position;
+        assert!(position.x > spawn.x);
+        let values = game
+            .watch
+            .interpreter
+            .run("print(roll, started, installed, value)")
+            .unwrap();
+        assert!(values.ends_with("False 1"));
+        drop(game);
+
+        let replay = Replay::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replay.frames.len(), 180);
+        let (world, tiles, rules) = start();
+        let mut game = replay.start(world, tiles, rules);
+        game.introduced = true;
+        for frame in &replay.frames {
+            Replay::step(&mut game, frame);
+        }
+        assert_eq!(game.player.position, position);
+        assert_eq!(game.save().to_json(), recorded);
+        assert_eq!(
+            game.watch
+                .interpreter
+                .run("print(roll, started, installed, value)")
+                .unwrap(),
+            values
+        );
+    }
+}
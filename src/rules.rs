@@ -0,0 +1,27 @@
+//! Rules of levels, set with level fields in LDtk. Not part of [`world::Level`], as `world.rs` is generated
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LevelRules {
+    /// How far from the player's center doors can be locked and unlocked, in pixels to each side.
+    /// Set with the `door_radius` field of the level, see [`tiles::in_reach`]
+    pub door_radius: f32,
+}
+
+impl Default for LevelRules {
+    fn default() -> Self {
+        Self { door_radius: 40.0 }
+    }
+}
+
+/// Rules of every level, by index. Levels without rules use the defaults
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rules {
+    pub levels: Vec<LevelRules>,
+}
+
+impl Rules {
+    pub fn get(&self, level: usize) -> LevelRules {
+        self.levels.get(level).copied().unwrap_or_default()
+    }
+}
@@ -0,0 +1,360 @@
+//! Saving and loading game progress
+use super::*;
+use serde::{Deserialize, Serialize};
+use watch::interpreter::pywatch::{self, Weather};
+
+/// Bump when the format changes. Saves with a different version are rejected
+pub const SAVE_VERSION: u32 = 1;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub const SAVE_PATH: &str = "garbage_collector3.save.json";
+#[cfg(target_arch = "wasm32")]
+pub const SAVE_KEY: &str = "garbage_collector3.save";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Save {
+    pub version: u32,
+
+    pub level_index: usize,
+    pub introduced: bool,
+    pub finished: bool,
+    pub timer: Option<f32>,
+    pub weather: Weather,
+    /// Levels where Void was already talked to and left
+    pub cleared_voids: Vec<usize>,
+    pub doors: Vec<Door>,
+
+    /// Modules of apps added with `watch.add_app`
+    pub apps: Vec<String>,
+    /// Code of the apps that were added with it, by module
+    #[serde(default)]
+    pub app_sources: std::collections::BTreeMap<String, String>,
+    /// Modules of apps (built-in ones too) which `tick` is not called
+    #[serde(default)]
+    pub background_disabled: Vec<String>,
+    /// Code ran in user's scope, replayed on load to restore it (and the apps defined there).
+    /// Code that only shows values isn't kept, see [`Interpreter::run_history`](watch::interpreter::Interpreter::run_history)
+    pub scripts: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Door {
+    pub level: usize,
+    /// Position of the bottom tile of the door
+    pub position: [i32; 2],
+    pub locked: bool,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Version(u32),
+    Invalid(String),
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to access save file: {}", err),
+            Self::Json(err) => write!(f, "failed to parse save file: {}", err),
+            Self::Version(version) => write!(
+                f,
+                "save file version {} is not supported (expected {})",
+                version, SAVE_VERSION
+            ),
+            Self::Invalid(message) => write!(f, "invalid save file: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<std::io::Error> for SaveError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl Save {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SaveError> {
+        #[derive(Deserialize)]
+        struct Header {
+            version: u32,
+        }
+
+        let header: Header = serde_json::from_str(json)?;
+        if header.version != SAVE_VERSION {
+            return Err(SaveError::Version(header.version));
+        }
+        Ok(serde_json::from_str(json)?)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn write(&self) -> Result<(), SaveError> {
+        Ok(std::fs::write(SAVE_PATH, self.to_json())?)
+    }
+
+    /// Read the save, if there is one
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read() -> Result<Option<Self>, SaveError> {
+        match std::fs::read_to_string(SAVE_PATH) {
+            Ok(json) => Self::from_json(&json).map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn write(&self) -> Result<(), SaveError> {
+        local_storage()?
+            .set_item(SAVE_KEY, &self.to_json())
+            .map_err(|err| SaveError::Invalid(format!("{:?}", err)))
+    }
+
+    /// Read the save, if there is one
+    #[cfg(target_arch = "wasm32")]
+    pub fn read() -> Result<Option<Self>, SaveError> {
+        match local_storage()?
+            .get_item(SAVE_KEY)
+            .map_err(|err| SaveError::Invalid(format!("{:?}", err)))?
+        {
+            Some(json) => Self::from_json(&json).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Result<web_sys::Storage, SaveError> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or_else(|| SaveError::Invalid("local storage is not available".to_owned()))
+}
+
+impl Game {
+    /// Save current progress. Dialogues are not saved, so an unfinished introduction will start over
+    pub fn save(&self) -> Save {
+        let mut doors = Vec::new();
+        for (index, level) in self.world.levels.iter().enumerate() {
+            doors.extend(self.tiles.doors(level).into_iter().map(|door| Door {
+                level: index,
+                position: [door.bottom.x, door.bottom.y],
+                locked: door.locked,
+            }));
+        }
+
+        let cleared_voids = self
+            .world
+            .levels
+            .iter()
+            .enumerate()
+            .filter(|(_, level)| {
+                !level
+                    .entities
+                    .iter()
+                    .any(|entity| matches!(entity.entity, world::Entity::Void(_)))
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        let builtin_apps = Watch::default_apps();
+        Save {
+            version: SAVE_VERSION,
+
+            level_index: self.level_index,
+            introduced: self.introduced && self.timer.is_some(),
+            finished: self.finished,
+            timer: self.timer,
+            weather: self.watch.interpreter.weather,
+            cleared_voids,
+            doors,
+
+            apps: self
+                .watch
+                .apps
+                .iter()
+                .filter(|app| {
+                    !builtin_apps
+                        .iter()
+                        .any(|builtin| builtin.module == app.module)
+                })
+                .map(|app| app.module.to_owned())
+                .collect(),
+            app_sources: self
+                .watch
+                .apps
+                .iter()
+                .filter_map(|app| Some((app.module.to_owned(), app.source.clone()?)))
+                .collect(),
+            background_disabled: self
+                .watch
+                .apps
+                .iter()
+                .filter(|app| !app.background)
+                .map(|app| app.module.to_owned())
+                .collect(),
+            scripts: self.watch.interpreter.run_history.clone(),
+        }
+    }
+
+    /// Create a game from a pristine world and a save
+    pub fn from_save(
+        world: levels::World,
+        tiles: tiles::TileTable,
+        rules: rules::Rules,
+        viewport: Vec2,
+        save: Save,
+    ) -> Result<Self, SaveError> {
+        if save.level_index >= world.len() {
+            return Err(SaveError::Invalid(format!(
+                "level {} does not exist",
+                save.level_index
+            )));
+        }
+
+        let mut game = Self::new(world, viewport)
+            .with_tiles(tiles)
+            .with_rules(rules);
+        game.level_index = save.level_index;
+        game.introduced = save.introduced;
+        game.finished = save.finished;
+        game.timer = save.timer;
+        game.player.teleport(get_player_start_position(
+            &game.world[game.level_index].entities,
+        ));
+        game.camera = game.player.position - game.screen_size() / 2.0;
+
+        for index in save.cleared_voids {
+            if let Some(level) = game.world.get_mut(index) {
+                level
+                    .entities
+                    .retain(|entity| !matches!(entity.entity, world::Entity::Void(_)));
+            }
+        }
+        for door in save.doors {
+            if let Some(level) = game.world.levels.get_mut(door.level) {
+                let position = IVec2::new(door.position[0], door.position[1]);
+                game.tiles.set_door(level, position, door.locked);
+            }
+        }
+
+        game.watch.interpreter.initialize();
+        for script in &save.scripts {
+            let _ = game.watch.interpreter.run(script);
+            // Replayed scripts might have queued actions, but their effects are already restored.
+            // Sounds are the exception, as they are not saved (images don't go through the queue)
+            let context = game.watch.interpreter.context.clone();
+            let queue = std::mem::take(&mut context.action_queue.lock().unwrap().queue);
+            let (loads, actions): (Vec<_>, Vec<_>) = queue
+                .into_iter()
+                .partition(|action| matches!(action, pywatch::Action::LoadSound(_)));
+            context.action_queue.lock().unwrap().queue.extend(loads);
+            // Apps take objects from user's scope as they are when added, so later scripts can't change them
+            for action in actions {
+                if let pywatch::Action::AddApp(module, _) = action {
+                    if save.apps.contains(&module) {
+                        restore_app(&mut game.watch, &module, save.app_sources.get(&module));
+                    }
+                }
+            }
+        }
+        for module in &save.apps {
+            restore_app(&mut game.watch, module, save.app_sources.get(module));
+        }
+        for app in &mut game.watch.apps {
+            app.background = !save
+                .background_disabled
+                .iter()
+                .any(|module| module == app.module);
+        }
+        game.watch.interpreter.weather = save.weather;
+
+        Ok(game)
+    }
+}
+
+/// Add a saved app, unless it's already there
+fn restore_app(watch: &mut Watch, module: &str, source: Option<&String>) {
+    if watch.apps.iter().any(|app| app.module == module)
+        || !watch
+            .interpreter
+            .install_app(module, source.map(String::as_str))
+    {
+        return;
+    }
+    let mut app = watch::App::new(
+        UVec2::new(4, 0),
+        Box::leak(Box::new(module.to_owned())).as_str(),
+    );
+    app.source = source.cloned();
+    watch.apps.push(app);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load() -> (levels::World, tiles::TileTable, rules::Rules) {
+        ldtk::load_file("src/world.ldtk").unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let (world, tiles, rules) = load();
+        let mut game = Game::new(world, Vec2::new(854.0, 480.0))
+            .with_tiles(tiles)
+            .with_rules(rules);
+        game.introduced = true;
+        game.timer = Some(12.5);
+        game.level_index = 2;
+        let level = &mut game.world[2];
+        let door = game.tiles.doors(level)[0];
+        assert!(game.tiles.set_door(level, door.bottom, !door.locked));
+        game.watch.interpreter.initialize();
+        game.watch.interpreter.run("answer = 6 * 7").unwrap();
+        // Looking at values and mistyped code don't have to be replayed on load
+        game.watch
+            .interpreter
+            .run("print(answer, len([answer]))")
+            .unwrap();
+        game.watch.interpreter.run("answer").unwrap();
+        game.watch.interpreter.run("answer =").unwrap_err();
+        assert_eq!(game.save().scripts, ["answer = 6 * 7"]);
+        let json = game.save().to_json();
+        drop(game);
+
+        let (world, tiles, rules) = load();
+        let save = Save::from_json(&json).unwrap();
+        let mut game = Game::from_save(world, tiles, rules, Vec2::new(854.0, 480.0), save).unwrap();
+        assert_eq!(game.save().to_json(), json);
+        assert_eq!(game.level_index, 2);
+        assert_eq!(game.tiles.doors(&game.world[2])[0].locked, !door.locked);
+        assert_eq!(game.watch.interpreter.run("print(answer)").unwrap(), "42");
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let (world, _, _) = load();
+        let game = Game::new(world, Vec2::new(854.0, 480.0));
+        let json = game.save().to_json().replacen(
+            &format!("\"version\": {}", SAVE_VERSION),
+            &format!("\"version\": {}", SAVE_VERSION + 1),
+            1,
+        );
+        assert!(matches!(
+            Save::from_json(&json),
+            Err(SaveError::Version(version)) if version == SAVE_VERSION + 1
+        ));
+    }
+}
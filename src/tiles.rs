@@ -0,0 +1,211 @@
+//! Gameplay properties of tileset tiles, so the game doesn't depend on where tiles are in the tileset.
+//! Read from enum tags and custom data of the tileset in LDtk
+use super::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TileProperties {
+    pub solid: bool,
+    pub slippery: bool,
+    /// Doors are locked while they are solid
+    pub door: bool,
+    /// Tile a door turns into when it is locked or unlocked
+    pub toggled: Option<UVec2>,
+    pub freezable: bool,
+    /// Tile this turns into when it snows
+    pub frozen: Option<UVec2>,
+    /// Tile this turns back into when it stops snowing, filled in from [`TileProperties::frozen`]
+    pub thawed: Option<UVec2>,
+    /// Particles die when they fly into this tile
+    pub blocks_particles: bool,
+}
+
+/// A door in the foreground: a column of door tiles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Door {
+    /// Index of the bottom tile in the level, stays the same when the door is locked or unlocked
+    pub id: usize,
+    /// Position of the bottom tile
+    pub bottom: IVec2,
+    /// Center of the door, in pixels
+    pub center: Vec2,
+    pub locked: bool,
+}
+
+impl Door {
+    /// Whether the door can be locked and unlocked from `origin`, see [`in_reach`]
+    pub fn in_reach(&self, origin: Vec2, radius: f32, grid_size: Vec2) -> bool {
+        in_reach(self.bottom, origin, radius, grid_size)
+    }
+}
+
+/// Whether the bottom tile of a door is in reach of `origin`: it has to be in the square going
+/// `radius` pixels from `origin` to each side, rounded out to whole tiles
+pub fn in_reach(bottom: IVec2, origin: Vec2, radius: f32, grid_size: Vec2) -> bool {
+    let tl = origin - Vec2::new(radius, radius);
+    let br = origin + Vec2::new(radius, radius);
+    (tl.x / grid_size.x).floor() as i32 <= bottom.x
+        && (tl.y / grid_size.y).floor() as i32 <= bottom.y
+        && bottom.x < (br.x / grid_size.x).ceil() as i32
+        && bottom.y < (br.y / grid_size.y).ceil() as i32
+}
+
+/// Properties of every tile in a tileset
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileTable {
+    /// Size of the tileset in tiles
+    pub size: UVec2,
+    pub properties: Vec<TileProperties>,
+}
+
+impl TileTable {
+    pub fn new(size: UVec2) -> Self {
+        Self {
+            size,
+            properties: vec![TileProperties::default(); (size.x * size.y) as usize],
+        }
+    }
+
+    /// Properties of the tile at `tile` in the tileset. Tiles outside of it have none
+    pub fn get(&self, tile: UVec2) -> TileProperties {
+        if tile.x >= self.size.x || tile.y >= self.size.y {
+            return TileProperties::default();
+        }
+        self.properties[(tile.x + tile.y * self.size.x) as usize]
+    }
+
+    pub fn get_mut(&mut self, tile: UVec2) -> Option<&mut TileProperties> {
+        if tile.x >= self.size.x || tile.y >= self.size.y {
+            return None;
+        }
+        self.properties
+            .get_mut((tile.x + tile.y * self.size.x) as usize)
+    }
+
+    /// Fill in [`TileProperties::thawed`] of frozen tiles
+    pub fn link(&mut self) {
+        for y in 0..self.size.y {
+            for x in 0..self.size.x {
+                let tile = UVec2::new(x, y);
+                if let Some(frozen) = self.get(tile).frozen {
+                    if let Some(properties) = self.get_mut(frozen) {
+                        properties.thawed = Some(tile);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Table of the built-in tileset, same as the tags in `src/world.ldtk`
+    pub fn builtin() -> Self {
+        let mut table = Self::new(UVec2::new(12, 4));
+        let mut set = |tile: UVec2, set: &dyn Fn(&mut TileProperties)| {
+            set(table.get_mut(tile).unwrap());
+        };
+
+        // Ground autotiles get covered in snow
+        for y in 0..=1 {
+            for x in 0..=3 {
+                set(UVec2::new(x, y), &|tile| {
+                    tile.freezable = true;
+                    tile.frozen = Some(UVec2::new(x, y + 2));
+                });
+            }
+        }
+        // Water freezes into ice
+        set(UVec2::new(7, 0), &|tile| {
+            tile.freezable = true;
+            tile.frozen = Some(UVec2::new(7, 1));
+            tile.blocks_particles = true;
+        });
+        set(UVec2::new(7, 1), &|tile| {
+            tile.solid = true;
+            tile.slippery = true;
+            tile.blocks_particles = true;
+        });
+        // Locked doors (top and bottom), then unlocked ones
+        for y in 2..=3 {
+            set(UVec2::new(7, y), &|tile| {
+                tile.solid = true;
+                tile.door = true;
+                tile.toggled = Some(UVec2::new(8, y));
+                tile.blocks_particles = true;
+            });
+            set(UVec2::new(8, y), &|tile| {
+                tile.door = true;
+                tile.toggled = Some(UVec2::new(7, y));
+            });
+        }
+
+        table.link();
+        table
+    }
+
+    /// Lock or unlock the door at `position` in the foreground, along with door tiles above and below it.
+    /// Returns true if anything changed
+    pub fn set_door(&self, level: &mut world::Level, position: IVec2, locked: bool) -> bool {
+        let is_door = |level: &world::Level, position: IVec2| {
+            level
+                .foreground
+                .get(position)
+                .is_some_and(|tile| self.get(tile.position).door)
+        };
+        if !is_door(level, position) {
+            return false;
+        }
+
+        let mut top = position;
+        while is_door(level, top - IVec2::new_y(1)) {
+            top.y -= 1;
+        }
+        let mut changed = false;
+        let mut position = top;
+        while is_door(level, position) {
+            let tile = level.foreground.get_mut(position).unwrap();
+            let properties = self.get(tile.position);
+            if properties.solid != locked {
+                if let Some(toggled) = properties.toggled {
+                    tile.position = toggled;
+                    changed = true;
+                }
+            }
+            position.y += 1;
+        }
+        changed
+    }
+
+    /// Doors of a level, in the order of their bottom tiles
+    pub fn doors(&self, level: &world::Level) -> Vec<Door> {
+        let size = level.foreground.size;
+        let grid_size = level.foreground.grid_size().into_f32();
+        let is_door = |position: IVec2| {
+            level
+                .foreground
+                .get(position)
+                .is_some_and(|tile| self.get(tile.position).door)
+        };
+        let mut doors = Vec::new();
+        for (position, tile) in level
+            .foreground
+            .rect(IVec2::ZERO, size)
+            .filter_map(|(position, tile)| Some((position, tile?)))
+        {
+            if !is_door(position) || is_door(position + IVec2::new_y(1)) {
+                continue;
+            }
+            let mut top = position;
+            while is_door(top - IVec2::new_y(1)) {
+                top.y -= 1;
+            }
+            doors.push(Door {
+                id: (position.x + position.y * size.x as i32) as usize,
+                bottom: position,
+                center: Vec2::new(
+                    (position.x as f32 + 0.5) * grid_size.x,
+                    (top.y + position.y + 1) as f32 / 2.0 * grid_size.y,
+                ),
+                locked: self.get(tile.position).solid,
+            });
+        }
+        doors
+    }
+}
@@ -0,0 +1,141 @@
+//! Images apps draw into pixel by pixel, uploaded to the GPU again when they change
+use super::*;
+
+/// Largest width and height of a canvas
+pub const MAX_CANVAS_SIZE: u32 = 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Canvas {
+    pub size: UVec2,
+    /// RGBA, row by row
+    pub pixels: Vec<u8>,
+    /// Changed since it was last uploaded
+    pub dirty: bool,
+}
+
+impl Canvas {
+    /// Transparent canvas
+    pub fn new(size: UVec2) -> Self {
+        Self {
+            size,
+            pixels: vec![0; (size.x * size.y * 4) as usize],
+            dirty: true,
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        (x >= 0 && y >= 0 && (x as u32) < self.size.x && (y as u32) < self.size.y)
+            .then(|| (x as usize + y as usize * self.size.x as usize) * 4)
+    }
+
+    /// Pixels outside of the canvas are ignored
+    pub fn set_pixel(&mut self, x: i32, y: i32, rgba: [u8; 4]) {
+        if let Some(index) = self.index(x, y) {
+            self.pixels[index..index + 4].copy_from_slice(&rgba);
+            self.dirty = true;
+        }
+    }
+
+    /// RGBA, None outside of the canvas
+    pub fn pixel(&self, x: i32, y: i32) -> Option<[u8; 4]> {
+        let index = self.index(x, y)?;
+        Some(self.pixels[index..index + 4].try_into().unwrap())
+    }
+
+    pub fn fill(&mut self, rgba: [u8; 4]) {
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&rgba);
+        }
+        self.dirty = true;
+    }
+
+    /// Copy pixels of another canvas, replacing these (alpha is copied, not blended)
+    pub fn blit(&mut self, source: &Canvas, position: IVec2) {
+        // Part of the source that lands on this canvas, in source coordinates.
+        // i64, so positions far outside of the canvas don't overflow
+        let visible = |position: i32, size: u32, source_size: u32| {
+            let position = position as i64;
+            (-position).max(0)..(size as i64 - position).min(source_size as i64)
+        };
+        let columns = visible(position.x, self.size.x, source.size.x);
+        let rows = visible(position.y, self.size.y, source.size.y);
+        if columns.is_empty() {
+            return;
+        }
+        let width = (columns.end - columns.start) as usize;
+        for y in rows {
+            let row = |canvas: &Canvas, x: i64, y: i64| {
+                let start = canvas.index(x as i32, y as i32).unwrap();
+                start..start + width * 4
+            };
+            let from = row(source, columns.start, y);
+            let to = row(
+                self,
+                columns.start + position.x as i64,
+                y + position.y as i64,
+            );
+            self.pixels[to].copy_from_slice(&source.pixels[from]);
+        }
+        self.dirty = true;
+    }
+
+    /// Replace all the pixels with RGBA data
+    pub fn set_bytes(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != self.pixels.len() {
+            return Err(format!(
+                "Expected {} bytes of RGBA for a {}x{} canvas, got {}",
+                self.pixels.len(),
+                self.size.x,
+                self.size.y,
+                data.len()
+            ));
+        }
+        self.pixels.copy_from_slice(data);
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Interpreter;
+
+    #[test]
+    fn colors_are_rgb_with_alpha() {
+        let mut interpreter = Interpreter::default();
+        interpreter.initialize();
+        let output = interpreter.run(
+            "canvas = watch.create_canvas(2, 1)\n\
+             watch.fill(canvas, 0x102030)\n\
+             watch.set_pixel(canvas, 1, 0, 0xff0000, 0.2)\n\
+             print(watch.get_pixel(canvas, 0, 0), watch.get_pixel(canvas, 1, 0), watch.get_pixel(canvas, 2, 0))\n\
+             print(watch.image_size(canvas))",
+        );
+        assert_eq!(
+            output,
+            Ok("(1056816, 1.0) (16711680, 0.2) NoneVector2(2.0, 1.0)".to_owned())
+        );
+        let canvases = interpreter.context.canvases.lock().unwrap();
+        assert_eq!(
+            canvases.values().next().unwrap().pixels,
+            [0x10, 0x20, 0x30, 255, 255, 0, 0, 51]
+        );
+    }
+
+    #[test]
+    fn invalid_colors_and_images_raise() {
+        let mut interpreter = Interpreter::default();
+        interpreter
+            .run("canvas = watch.create_canvas(1, 1)")
+            .unwrap();
+        for code in [
+            "watch.set_pixel(canvas, 0, 0, 0xff0000ff)",
+            "watch.fill(canvas, 0xffffff, 2)",
+            "watch.image_size(canvas + 1)",
+            "watch.load_image(b'not an image')",
+        ] {
+            let error = interpreter.run(code).unwrap_err();
+            assert!(error.contains("ValueError"), "{}: {}", code, error);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Events of the game, which Python subscribes to with `watch.on`
+use super::*;
+use pywatch::{PyVec2, Weather};
+use vm::builtins::PyTupleRef;
+use vm::convert::ToPyObject;
+use vm::TryFromObject;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// Index of the level
+    LevelEntered(usize),
+    /// Index of the level and the timer
+    LevelFinished(usize, Option<f32>),
+    /// Position of the player
+    Landed(Vec2),
+    /// Position of the player
+    WallKick(Vec2),
+    /// Id of the door and its center, in pixels
+    DoorLocked(usize, Vec2),
+    DoorUnlocked(usize, Vec2),
+    WeatherChanged(Weather),
+    /// Index of the level the dialogue was in
+    DialogueFinished(usize),
+    /// Position the platform arrived at and the result of its condition
+    PlatformArrived(Vec2, bool),
+}
+
+impl Event {
+    /// Names of the events, as they are passed to `watch.on`
+    pub const NAMES: &'static [&'static str] = &[
+        "level_entered",
+        "level_finished",
+        "landed",
+        "wall_kick",
+        "door_locked",
+        "door_unlocked",
+        "weather_changed",
+        "dialogue_finished",
+        "platform_arrived",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::LevelEntered(_) => "level_entered",
+            Self::LevelFinished(..) => "level_finished",
+            Self::Landed(_) => "landed",
+            Self::WallKick(_) => "wall_kick",
+            Self::DoorLocked(..) => "door_locked",
+            Self::DoorUnlocked(..) => "door_unlocked",
+            Self::WeatherChanged(_) => "weather_changed",
+            Self::DialogueFinished(_) => "dialogue_finished",
+            Self::PlatformArrived(..) => "platform_arrived",
+        }
+    }
+
+    /// Dictionary passed to the listeners
+    fn payload(&self, vm: &vm::VirtualMachine) -> vm::PyResult<vm::builtins::PyDictRef> {
+        let payload = vm.ctx.new_dict();
+        let set = |key: &str, value: vm::PyObjectRef| payload.set_item(key, value, vm);
+        match *self {
+            Self::LevelEntered(level) | Self::DialogueFinished(level) => {
+                set("level", level.to_pyobject(vm))?
+            }
+            Self::LevelFinished(level, timer) => {
+                set("level", level.to_pyobject(vm))?;
+                set("timer", timer.to_pyobject(vm))?;
+            }
+            Self::Landed(position) | Self::WallKick(position) => {
+                set("position", PyVec2(position).to_pyobject(vm))?
+            }
+            Self::DoorLocked(id, position) | Self::DoorUnlocked(id, position) => {
+                set("id", id.to_pyobject(vm))?;
+                set("position", PyVec2(position).to_pyobject(vm))?;
+            }
+            Self::WeatherChanged(weather) => {
+                let weather = match weather {
+                    Weather::Sunny => "sunny",
+                    Weather::Rainy => "rainy",
+                    Weather::Snowy => "snowy",
+                };
+                set("weather", weather.to_pyobject(vm))?
+            }
+            Self::PlatformArrived(position, condition) => {
+                set("position", PyVec2(position).to_pyobject(vm))?;
+                set("condition", condition.to_pyobject(vm))?;
+            }
+        }
+        Ok(payload)
+    }
+}
+
+impl Interpreter {
+    /// Call listeners of the events raised since the last frame, in the order they were raised
+    pub fn dispatch_events(&mut self) {
+        for event in std::mem::take(&mut self.events) {
+            let Some(listeners) = self.enter(|vm| {
+                let mut listeners = Vec::new();
+                for (_, entry) in &pywatch::listeners(vm)? {
+                    let entry = PyTupleRef::try_from_object(vm, entry)?;
+                    let [name, callable] = entry.as_slice() else {
+                        return Err(vm.new_type_error("Invalid listener".to_owned()));
+                    };
+                    if String::try_from_object(vm, name.clone())? == event.name() {
+                        listeners.push(callable.clone());
+                    }
+                }
+                Ok(listeners)
+            }) else {
+                continue;
+            };
+            for listener in listeners {
+                self.enter(|vm| listener.call((event.payload(vm)?,), vm));
+            }
+        }
+    }
+}
@@ -2,25 +2,62 @@ use super::*;
 use rustpython_vm as vm;
 use vm::scope::Scope;
 
+pub mod canvas;
+pub mod events;
+pub mod platforms;
 pub mod pywatch;
+pub mod state;
+pub mod text;
+pub mod timers;
+pub mod watchdog;
 
 pub struct Interpreter {
     pub interpreter: vm::Interpreter,
+    /// What Python code of this interpreter shares with the game
+    pub context: std::sync::Arc<pywatch::Context>,
     pub initialized: bool,
+    pub watchdog: watchdog::Watchdog,
 
     pub renderer: pywatch::Renderer,
     pub current_app: Option<rustpython_vm::PyObjectRef>,
     pub player_scope: Scope,
+    /// All the code that was ran in [`Interpreter::player_scope`], in order. Replaying it restores the scope.
+    /// Code that can't change it is left out, see [`changes_scope`]
+    pub run_history: Vec<String>,
+    /// Code queued with `watch.run` and ran by the last [`Interpreter::update_queue`], with its place in the queue
+    pub recorded_runs: Vec<replay::ReplayRun>,
+    /// When replaying, code to run instead of the code queued with `watch.run` this frame
+    pub replayed_runs: Option<Vec<replay::ReplayRun>>,
+    /// When replaying, what `watch.time(real=True)` returns this frame instead of the real time
+    pub replayed_real_time: Option<f64>,
+    /// Sounds of actions since the last frame
+    pub effects: Vec<audio::Effect>,
+    /// Sounds loaded by Python, indexed by [`pywatch::PySound`]
+    pub sounds: Vec<std::sync::Arc<audio::Sound>>,
+    /// Sounds played by Python since the last frame, with their volume
+    pub played_sounds: Vec<(std::sync::Arc<audio::Sound>, f32)>,
+    /// Compiled once per level
+    pub platform_conditions: Option<platforms::PlatformConditions>,
+    /// Events since the last frame, see [`Interpreter::dispatch_events`]
+    pub events: Vec<events::Event>,
+    /// Set by Python with `watch.set_weather`
+    pub weather: pywatch::Weather,
+    /// Tiles changed since [`pywatch::Context::state`] was taken, so it has to be taken again instead of updated
+    pub state_outdated: bool,
 }
 
 impl Default for Interpreter {
     fn default() -> Self {
+        let (sender, receiver) = vm::signal::user_signal_channel();
         let interpreter = rustpython::InterpreterConfig::new()
             .init_stdlib()
+            .init_hook(Box::new(move |vm| vm.set_user_signal_channel(receiver)))
             .add_native_module("watch".to_owned(), pywatch::make_module)
             .interpreter();
         let player_scope = interpreter.enter(|vm| vm.new_scope_with_builtins());
         interpreter.enter(|vm| {
+            let check = vm.new_function("__budget__", watchdog::check);
+            vm.builtins.set_attr("__budget__", check, vm).unwrap();
             if let Err(err) = vm.run_code_string(
                 player_scope.clone(),
                 "import watch\ndef print(*args): watch.print(' '.join(str(arg) for arg in args))",
@@ -29,123 +66,362 @@ impl Default for Interpreter {
                 vm.print_exception(err);
             }
         });
+        let context = std::sync::Arc::new(pywatch::Context::default());
         Self {
             interpreter,
+            watchdog: watchdog::Watchdog::new(sender, context.budget.clone()),
+            context,
             initialized: false,
 
             renderer: pywatch::Renderer::default(),
             current_app: None,
             player_scope,
+            run_history: Vec::new(),
+            recorded_runs: Vec::new(),
+            replayed_runs: None,
+            replayed_real_time: None,
+            effects: Vec::new(),
+            sounds: Vec::new(),
+            played_sounds: Vec::new(),
+            platform_conditions: None,
+            events: Vec::new(),
+            weather: pywatch::Weather::default(),
+            state_outdated: false,
         }
     }
 }
 
 impl Interpreter {
+    /// Import built-in apps. Does nothing if already initialized
+    pub fn initialize(&mut self) {
+        if self.initialized {
+            return;
+        }
+        self.enter_unguarded(|vm| {
+            macro_rules! import {
+                ($($name:ident = $path:literal)*) => {
+                    $(vm::import::import_codeobj(
+                        vm,
+                        stringify!($name),
+                        vm.ctx.new_code(vm::py_compile!(file = $path)),
+                        true,
+                    )?;)*
+                };
+            }
+            import! {
+                vec = "apps/vec.py"
+                ui = "apps/ui.py"
+                placeholder = "apps/placeholder.py"
+
+                weather = "apps/weather.py"
+                keyring = "apps/keyring.py"
+                terminal = "apps/terminal.py"
+            }
+            Ok(())
+        });
+        self.initialized = true;
+    }
+
+    /// Seed Python's `random`, so replays of code that uses it play out the same
+    pub fn seed_random(&self, seed: u64) {
+        self.enter_unguarded(|vm| {
+            let random = vm.import("random", 0)?;
+            random.get_attr("seed", vm)?.call((seed,), vm)?;
+            Ok(())
+        });
+    }
+
+    /// Run code in [`Interpreter::player_scope`], returning everything it printed.
+    /// If it raised an exception, the output ends with it and is returned as an error
+    pub fn run(&mut self, code: &str) -> Result<String, String> {
+        *self.context.capture_output.lock().unwrap() = Some(String::new());
+        let result = self.enter(|vm| {
+            let code_obj =
+                watchdog::compile(vm, code, vm::compiler::Mode::Exec, "<stdin>".to_owned())
+                    .map_err(|err| vm.new_syntax_error(&err, Some(code)))?;
+            vm.run_code_obj(code_obj, self.player_scope.clone())
+        });
+        if changes_scope(code) {
+            self.run_history.push(code.to_owned());
+        }
+        let output = self.context.capture_output.lock().unwrap().take().unwrap();
+        match result {
+            Some(_) => Ok(output),
+            None => Err(output),
+        }
+    }
+
+    /// Make `module` importable as an app, with its own namespace. With `source`, a new module is made from it.
+    /// Otherwise it has to be an existing module, or an object in [`Interpreter::player_scope`],
+    /// which is taken as it is now, so later code ran there doesn't replace it. Returns false if it failed
+    pub fn install_app(&self, module: &str, source: Option<&str>) -> bool {
+        self.enter(|vm| {
+            let modules = vm.sys_module.get_attr("modules", vm)?;
+            if let Some(source) = source {
+                let globals = vm.ctx.new_dict();
+                let app = vm.new_module(module, globals.clone(), None);
+                let code = watchdog::compile(
+                    vm,
+                    source,
+                    vm::compiler::Mode::Exec,
+                    format!("<{}>", module),
+                )
+                .map_err(|err| vm.new_syntax_error(&err, Some(source)))?;
+                vm.run_code_obj(code, Scope::with_builtins(None, globals, vm))?;
+                modules.set_item(module, app.into(), vm)?;
+            } else if vm.import(&vm.ctx.new_str(module), 0).is_err() {
+                let app = self
+                    .player_scope
+                    .globals
+                    .get_item_opt(module, vm)?
+                    .ok_or_else(|| {
+                        vm.new_name_error(
+                            format!("No module or object named {:?}", module),
+                            vm.ctx.new_str(module),
+                        )
+                    })?;
+                modules.set_item(module, app, vm)?;
+            }
+            Ok(())
+        })
+        .is_some()
+    }
+
+    /// Returns true if tiles of the level were changed
+    #[allow(clippy::too_many_arguments)]
     pub fn update(
         &mut self,
         delta_time: f32,
-        graphics: &mut Graphics2D,
+        level_index: usize,
+        identifier: &str,
+        rules: &rules::LevelRules,
         level: &mut world::Level,
+        tiles: &tiles::TileTable,
         player: &Player,
         apps: &mut Vec<App>,
-    ) {
-        if !self.initialized {
-            self.enter(|vm| {
-                macro_rules! import {
-                    ($($name:ident = $path:literal)*) => {
-                        $(vm::import::import_codeobj(
-                            vm,
-                            stringify!($name),
-                            vm.ctx.new_code(vm::py_compile!(file = $path)),
-                            true,
-                        )?;)*
-                    };
-                }
-                import! {
-                    vec = "apps/vec.py"
-                    ui = "apps/ui.py"
-                    placeholder = "apps/placeholder.py"
-
-                    weather = "apps/weather.py"
-                    keyring = "apps/keyring.py"
-                    terminal = "apps/terminal.py"
-                }
-                Ok(())
-            });
-            self.initialized = true;
+    ) -> bool {
+        self.initialize();
+        text::LAYOUTS.lock().unwrap().next_frame();
+        {
+            let mut clock = self.context.clock.lock().unwrap();
+            clock.real = self
+                .replayed_real_time
+                .unwrap_or_else(|| pywatch::real_time() - clock.start);
         }
-        self.update_queue(graphics, level, player, apps);
-
-        for entity in level.entities.entities_mut() {
-            if let world::Entity::Platform(platform) = &mut entity.entity {
-                let result = self.interpreter.enter(|vm| {
-                    vm.compile(
-                        &platform.condition,
-                        vm::compiler::Mode::Eval,
-                        "world.ldtk".to_owned(),
-                    )
-                    .ok()
-                    .and_then(|code_obj| vm.run_code_obj(code_obj, self.player_scope.clone()).ok())
-                    .and_then(|result| result.try_to_bool(vm).ok())
-                    .is_some_and(|result| result)
-                });
-                let target = if result {
-                    platform.point_true
-                } else {
-                    platform.point_false
-                };
-                let target = target.into_f32() * world::Entities::GRID_SIZE as f32;
-                let delta = target - entity.position;
-                if delta.magnitude_squared() > 2.0 {
-                    if let Some(dir) = delta.normalize() {
-                        entity.position += dir * 10.0 * delta_time;
-                    }
+        {
+            let mut state = self.context.state.lock().unwrap();
+            match &mut *state {
+                Some(state) if state.level_index == level_index && !self.state_outdated => {
+                    state.update(rules, level, player)
+                }
+                _ => {
+                    *state = Some(state::State::new(
+                        level_index,
+                        identifier,
+                        rules,
+                        level,
+                        tiles,
+                        player,
+                    ))
                 }
             }
         }
+        let tiles_changed = self.update_queue(rules, level, tiles, player, apps);
+        self.state_outdated = tiles_changed;
+
+        self.update_platforms(delta_time, identifier, level);
+        self.tick(delta_time, apps);
+        self.update_timers(delta_time);
+        self.dispatch_events();
+        tiles_changed
     }
 
-    pub fn frame(
-        &mut self,
-        camera: &mut Camera,
-        assets: &Assets,
-        screen_space: Rect,
-        frame: pywatch::Frame,
-    ) {
-        if let Some(module) = &self.current_app {
-            let result = self.enter(|vm| {
-                let frame_fn = module.get_attr("frame", vm)?;
-                let result = frame_fn.call((frame,), vm)?.try_to_bool(vm);
-                self.renderer.frame(vm, camera, assets, screen_space)?;
-                result
+    /// Call `tick` of every app that has it and runs in background, even if the watch is closed.
+    /// Apps which `tick` raises are taken out of background, so they don't fail every frame
+    pub fn tick(&self, delta_time: f32, apps: &mut [App]) {
+        for app in apps.iter_mut().filter(|app| app.background) {
+            let result = self.try_enter_within(watchdog::BACKGROUND_BUDGET, false, |vm| {
+                let module = vm.import(app.module, 0)?;
+                if let Some(tick) = vm.get_attribute_opt(module, "tick")? {
+                    tick.call((delta_time,), vm)?;
+                }
+                Ok(())
             });
-            if result != Some(true) {
-                self.current_app = None;
+            if let Err(message) = result {
+                log_error(&format!(
+                    "{}\nApp {} is taken out of background",
+                    message.trim_end(),
+                    app.module
+                ));
+                app.background = false;
             }
         }
     }
 
+    /// Call `frame` of the current app, closing it if it exits. If it raised, returns the formatted exception
+    pub fn frame(&mut self, frame: pywatch::Frame) -> Result<(), String> {
+        self.renderer.render_queue.lock().unwrap().clear();
+        let Some(module) = &self.current_app else {
+            return Ok(());
+        };
+        // Unless code that ran before in this frame was interrupted
+        let interrupt = self.context.budget.take_interrupt();
+        let result = self.try_enter(interrupt, |vm| {
+            let frame_fn = module.get_attr("frame", vm)?;
+            frame_fn.call((frame,), vm)?.try_to_bool(vm)
+        });
+        if result != Ok(true) {
+            self.current_app = None;
+        }
+        result
+            .map(|_| ())
+            .inspect_err(|message| log_error(message.trim_end()))
+    }
+
+    /// Like [`Interpreter::enter`] without a budget, for the game's own code: imports of built-in
+    /// apps and modules can take longer than it in debug builds
+    fn enter_unguarded<R>(
+        &self,
+        callback: impl FnOnce(&rustpython_vm::VirtualMachine) -> rustpython_vm::PyResult<R>,
+    ) -> Option<R> {
+        let _context = self.context.enter();
+        self.interpreter.enter(|vm| {
+            callback(vm)
+                .map_err(|err| {
+                    let mut message = String::new();
+                    vm.write_exception(&mut message, &err).unwrap();
+                    log_error(message.trim_end());
+                })
+                .ok()
+        })
+    }
+
+    /// Run a callback in the VM within [`watchdog::BUDGET`], printing the exception if it fails
     pub fn enter<R>(
         &self,
         callback: impl FnOnce(&rustpython_vm::VirtualMachine) -> rustpython_vm::PyResult<R>,
     ) -> Option<R> {
-        self.interpreter.enter(|vm| match callback(vm) {
-            Ok(val) => Some(val),
-            Err(err) => {
-                if let Some(output) = &mut *pywatch::CAPTURE_OUTPUT.lock().unwrap() {
-                    vm.write_exception(&mut *output, &err).unwrap();
-                    return None;
-                }
-                #[cfg(target_arch = "wasm32")]
-                {
-                    let mut out = String::new();
-                    vm.write_exception(&mut out, &err).unwrap();
-                    web_sys::console::error_1(&out.into());
+        self.enter_guarded(false, callback)
+    }
+
+    /// Like [`Interpreter::enter`], raising `KeyboardInterrupt` instead of running the callback if `interrupt` is set
+    pub fn enter_guarded<R>(
+        &self,
+        interrupt: bool,
+        callback: impl FnOnce(&rustpython_vm::VirtualMachine) -> rustpython_vm::PyResult<R>,
+    ) -> Option<R> {
+        self.try_enter(interrupt, callback)
+            .map_err(|message| {
+                if let Some(output) = &mut *self.context.capture_output.lock().unwrap() {
+                    output.push_str(&message);
+                } else {
+                    log_error(message.trim_end());
                 }
-                #[cfg(not(target_arch = "wasm32"))]
-                vm.print_exception(err);
-                None
-            }
+            })
+            .ok()
+    }
+
+    /// Like [`Interpreter::enter_guarded`], returning the formatted exception instead of printing it.
+    /// Every exception is added to [`pywatch::Context::error_log`]
+    pub fn try_enter<R>(
+        &self,
+        interrupt: bool,
+        callback: impl FnOnce(&rustpython_vm::VirtualMachine) -> rustpython_vm::PyResult<R>,
+    ) -> Result<R, String> {
+        self.try_enter_within(watchdog::BUDGET, interrupt, callback)
+    }
+
+    /// Like [`Interpreter::try_enter`], with a different budget
+    pub fn try_enter_within<R>(
+        &self,
+        budget: std::time::Duration,
+        interrupt: bool,
+        callback: impl FnOnce(&rustpython_vm::VirtualMachine) -> rustpython_vm::PyResult<R>,
+    ) -> Result<R, String> {
+        let _context = self.context.enter();
+        self.watchdog.guard_within(budget, || {
+            self.interpreter.enter(|vm| {
+                let result = if interrupt {
+                    Err(vm.new_exception_empty(vm.ctx.exceptions.keyboard_interrupt.to_owned()))
+                } else {
+                    callback(vm)
+                };
+                result.map_err(|err| {
+                    let mut message = String::new();
+                    vm.write_exception(&mut message, &err).unwrap();
+                    self.context.log_exception(message.clone());
+                    message
+                })
+            })
         })
     }
 }
+
+/// Builtins that only look at their arguments, so calling them can't change the scope
+const READ_ONLY_BUILTINS: &[&str] = &[
+    "print",
+    "len",
+    "repr",
+    "str",
+    "type",
+    "dir",
+    "help",
+    "id",
+    "abs",
+    "min",
+    "max",
+    "sum",
+    "sorted",
+    "round",
+    "int",
+    "float",
+    "bool",
+    "hex",
+    "bin",
+    "isinstance",
+    "hasattr",
+];
+
+/// Whether running `code` again could change the player's scope. Code that doesn't parse or only shows values
+/// (like `x`, `print(x + 1)` or `len(items)`) can't, so it isn't kept in [`Interpreter::run_history`] and saves
+/// don't grow with every look at a variable
+fn changes_scope(code: &str) -> bool {
+    use vm::compiler::parser::ast;
+
+    fn reads(expr: &ast::Expr) -> bool {
+        match expr {
+            ast::Expr::Constant(_) => true,
+            ast::Expr::Name(node) => matches!(node.ctx, ast::ExprContext::Load),
+            ast::Expr::Attribute(node) => reads(&node.value),
+            ast::Expr::Subscript(node) => reads(&node.value) && reads(&node.slice),
+            ast::Expr::Slice(node) => [&node.lower, &node.upper, &node.step]
+                .into_iter()
+                .flatten()
+                .all(|expr| reads(expr)),
+            ast::Expr::BinOp(node) => reads(&node.left) && reads(&node.right),
+            ast::Expr::UnaryOp(node) => reads(&node.operand),
+            ast::Expr::BoolOp(node) => node.values.iter().all(reads),
+            ast::Expr::Compare(node) => reads(&node.left) && node.comparators.iter().all(reads),
+            ast::Expr::Tuple(node) => node.elts.iter().all(reads),
+            ast::Expr::List(node) => node.elts.iter().all(reads),
+            ast::Expr::JoinedStr(node) => node.values.iter().all(reads),
+            ast::Expr::FormattedValue(node) => {
+                reads(&node.value) && node.format_spec.as_deref().is_none_or(reads)
+            }
+            ast::Expr::Call(node) => {
+                matches!(&*node.func, ast::Expr::Name(name) if READ_ONLY_BUILTINS.contains(&name.id.as_str()))
+                    && node.args.iter().all(reads)
+                    && node.keywords.iter().all(|keyword| reads(&keyword.value))
+            }
+            _ => false,
+        }
+    }
+
+    match vm::compiler::parser::parse(code, vm::compiler::Mode::Exec.into(), "<stdin>") {
+        Ok(ast::Mod::Module(module)) => !module
+            .body
+            .iter()
+            .all(|stmt| matches!(stmt, ast::Stmt::Expr(stmt) if reads(&stmt.value))),
+        _ => false,
+    }
+}
@@ -0,0 +1,181 @@
+//! Platforms move between two points, depending on a Python condition set in LDtk
+use super::*;
+use vm::builtins::PyCode;
+use vm::{AsObject, PyRef};
+
+/// Compiled condition of a platform
+pub struct PlatformCondition {
+    /// None if the condition failed to compile
+    pub code: Option<PyRef<PyCode>>,
+    /// Error the condition failed with last time, so it is only reported when it changes
+    pub error: Option<String>,
+    /// Whether the platform was at its target last frame, to know when it arrives
+    pub arrived: bool,
+}
+
+/// Conditions of the platforms of a level, in the order they are in
+pub struct PlatformConditions {
+    pub level: String,
+    pub conditions: Vec<PlatformCondition>,
+}
+
+/// Error message of a platform, with where to find it in LDtk
+fn describe(level: &str, position: Vec2, message: &str) -> String {
+    format!(
+        "{}: platform at ({:.0}, {:.0}): {}",
+        level, position.x, position.y, message
+    )
+}
+
+fn platforms(
+    level: &world::Level,
+) -> impl Iterator<Item = (&world::EntityObject, &world::Platform)> {
+    level
+        .entities
+        .entities()
+        .iter()
+        .filter_map(|entity| match &entity.entity {
+            world::Entity::Platform(platform) => Some((entity, platform)),
+            _ => None,
+        })
+}
+
+fn compile(vm: &vm::VirtualMachine, condition: &str) -> Result<PyRef<PyCode>, String> {
+    watchdog::compile(
+        vm,
+        condition,
+        vm::compiler::Mode::Eval,
+        "world.ldtk".to_owned(),
+    )
+    .map_err(|err| err.to_string())
+}
+
+impl Interpreter {
+    /// Compile platform conditions of every level, returning the errors
+    pub fn check_platforms(&self, world: &levels::World) -> Vec<String> {
+        self.interpreter.enter(|vm| {
+            world
+                .levels
+                .iter()
+                .zip(&world.identifiers)
+                .flat_map(|(level, identifier)| {
+                    platforms(level).filter_map(move |(entity, platform)| {
+                        let err = compile(vm, &platform.condition).err()?;
+                        Some(describe(identifier, entity.position, &err))
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn compile_platforms(&self, identifier: &str, level: &world::Level) -> PlatformConditions {
+        let conditions = self.interpreter.enter(|vm| {
+            platforms(level)
+                .map(
+                    |(entity, platform)| match compile(vm, &platform.condition) {
+                        Ok(code) => PlatformCondition {
+                            code: Some(code),
+                            error: None,
+                            arrived: true,
+                        },
+                        Err(err) => {
+                            log_error(&describe(identifier, entity.position, &err));
+                            PlatformCondition {
+                                code: None,
+                                error: Some(err),
+                                arrived: true,
+                            }
+                        }
+                    },
+                )
+                .collect()
+        });
+        PlatformConditions {
+            level: identifier.to_owned(),
+            conditions,
+        }
+    }
+
+    /// Evaluate a condition. Errors are returned as messages, except for `NameError`s:
+    /// variables in conditions are defined by the player, until then the condition is false
+    fn evaluate(&self, code: &PyRef<PyCode>) -> Result<bool, Option<String>> {
+        let _context = self.context.enter();
+        self.watchdog.guard(|| {
+            self.interpreter.enter(|vm| {
+                match vm
+                    .run_code_obj(code.clone(), self.player_scope.clone())
+                    .and_then(|result| result.try_to_bool(vm))
+                {
+                    Ok(result) => Ok(result),
+                    Err(err) if err.fast_isinstance(vm.ctx.exceptions.name_error) => Err(None),
+                    Err(err) => {
+                        let message = err
+                            .as_object()
+                            .str(vm)
+                            .map_or_else(|_| String::new(), |message| message.to_string());
+                        Err(Some(format!("{}: {}", err.class().name(), message)))
+                    }
+                }
+            })
+        })
+    }
+
+    /// Move platforms towards the point their condition selects
+    pub fn update_platforms(
+        &mut self,
+        delta_time: f32,
+        identifier: &str,
+        level: &mut world::Level,
+    ) {
+        let mut cache = match self.platform_conditions.take() {
+            Some(cache) if cache.level == identifier => cache,
+            _ => self.compile_platforms(identifier, level),
+        };
+
+        let platforms = level
+            .entities
+            .entities_mut()
+            .iter_mut()
+            .filter(|entity| matches!(entity.entity, world::Entity::Platform(_)));
+        for (entity, condition) in platforms.zip(&mut cache.conditions) {
+            let world::Entity::Platform(platform) = &entity.entity else {
+                continue;
+            };
+            let result = match &condition.code {
+                Some(code) => match self.evaluate(code) {
+                    Ok(result) => {
+                        condition.error = None;
+                        result
+                    }
+                    Err(err) => {
+                        if let Some(message) = err.as_deref().filter(|_| err != condition.error) {
+                            log_error(&describe(identifier, entity.position, message));
+                        }
+                        condition.error = err;
+                        false
+                    }
+                },
+                None => false,
+            };
+
+            let target = if result {
+                platform.point_true
+            } else {
+                platform.point_false
+            };
+            let target = target.into_f32() * world::Entities::GRID_SIZE as f32;
+            let delta = target - entity.position;
+            let arrived = delta.magnitude_squared() <= 2.0;
+            if !arrived {
+                if let Some(dir) = delta.normalize() {
+                    entity.position += dir * 10.0 * delta_time;
+                }
+            } else if !condition.arrived {
+                self.events
+                    .push(events::Event::PlatformArrived(entity.position, result));
+            }
+            condition.arrived = arrived;
+        }
+        self.platform_conditions = Some(cache);
+    }
+}
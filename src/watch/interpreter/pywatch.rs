@@ -1,33 +1,143 @@
 #![vm::pymodule]
 use super::vm;
 use super::*;
-use speedy2d::font::TextLayout;
+use serde::{Deserialize, Serialize};
 use std::sync::{Arc, LazyLock, Mutex};
 use vm::convert::ToPyObject;
+use vm::function::{ArgIntoFloat, OptionalArg};
 use vm::*;
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Action {
-    LoadImage(Vec<u8>),
+    /// Sounds are decoded right away, so errors are reported to the caller
+    LoadSound(Arc<audio::Sound>),
+    PlaySound(PySound, f32),
+    PlayTone(audio::Tone),
     UnlockNearest,
     LockNearest,
+    /// Id of a door and whether it should be locked
+    SetDoor(usize, bool),
     Run(String),
-    AddApp(String),
+    /// Module name and its code
+    AddApp(String, Option<String>),
+    /// Module name and whether its `tick` should be called
+    SetBackground(String, bool),
+    SetWeather(Weather),
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct ActionQueue {
     pub queue: Vec<Action>,
-    pub next_image_index: PyImage,
+    pub next_sound_index: PySound,
 }
 
-pub static ACTION_QUEUE: LazyLock<Arc<Mutex<ActionQueue>>> =
-    LazyLock::new(|| Arc::new(Mutex::new(ActionQueue::default())));
+/// Everything the `watch` module shares with the game. Every [`super::Interpreter`] has its own,
+/// which is current while it runs Python code, so games in the same process don't share anything
+#[derive(Default)]
+pub struct Context {
+    pub action_queue: Mutex<ActionQueue>,
+    /// Sizes of every image, indexed by handle. Images are decoded when loaded, so they can be
+    /// used right away
+    pub image_size: Mutex<Vec<Vec2>>,
+    /// Images waiting to be uploaded to the GPU, in order
+    pub new_images: Mutex<Vec<NewImage>>,
+    /// Output of `print`, while [`super::Interpreter::run`] captures it
+    pub capture_output: Mutex<Option<String>>,
+    /// Whether the last code ran with `watch.run` raised an exception
+    pub run_failed: Mutex<bool>,
+    pub clock: Mutex<Clock>,
+    pub next_listener: Mutex<PyListener>,
+    /// Last exceptions raised in Python, formatted, oldest first
+    pub error_log: Mutex<std::collections::VecDeque<String>>,
+    /// Copy of the game Python reads, see [`super::state`]
+    pub state: Mutex<Option<super::state::State>>,
+    /// Pixels of canvas images
+    pub canvases: Mutex<std::collections::HashMap<PyImage, super::canvas::Canvas>>,
+    /// Of the call that is running, checked by `__budget__()`
+    pub budget: Arc<super::watchdog::Budget>,
+}
+
+/// Size and decoded RGBA of an image, or None for a canvas, as its pixels are in
+/// [`Context::canvases`]
+pub type NewImage = Option<(UVec2, Vec<u8>)>;
+
+thread_local! {
+    static CURRENT: std::cell::RefCell<Option<Arc<Context>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Context of the interpreter that is running Python code on this thread
+pub fn context() -> Arc<Context> {
+    CURRENT
+        .with_borrow(|current| current.clone())
+        .expect("the watch module is only used by a running interpreter")
+}
+
+/// Makes the previous context current again when dropped
+pub struct ContextGuard(Option<Arc<Context>>);
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        CURRENT.set(self.0.take());
+    }
+}
 
-pub static IMAGE_SIZE: LazyLock<Arc<Mutex<Vec<Vec2>>>> =
-    LazyLock::new(|| Arc::new(Mutex::new(Vec::new())));
+impl Context {
+    /// Make this the current context, until the guard is dropped
+    pub fn enter(self: &Arc<Self>) -> ContextGuard {
+        ContextGuard(CURRENT.replace(Some(self.clone())))
+    }
+
+    pub fn queue(&self, action: Action) {
+        self.action_queue.lock().unwrap().queue.push(action);
+    }
 
-pub static CAPTURE_OUTPUT: LazyLock<Mutex<Option<String>>> = LazyLock::new(|| Mutex::new(None));
+    pub fn log_exception(&self, message: String) {
+        let mut log = self.error_log.lock().unwrap();
+        if log.len() >= ERROR_LOG_SIZE {
+            log.pop_front();
+        }
+        log.push_back(message);
+    }
+}
+
+/// Time since the interpreter was created, in seconds
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    /// Advanced with the time steps of the game, so it stops when the game does
+    pub game: f64,
+    /// [`real_time`] when the interpreter was created
+    pub start: f64,
+    /// Real time since `start` as of the start of the frame. Taken once a frame, so replays can record it
+    pub real: f64,
+    pub next_timer: PyTimer,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self {
+            game: 0.0,
+            start: real_time(),
+            real: 0.0,
+            next_timer: 0,
+        }
+    }
+}
+
+/// Wall clock time in seconds, from an arbitrary point
+pub fn real_time() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    return web_sys::window()
+        .and_then(|window| window.performance())
+        .map_or(0.0, |performance| performance.now() / 1000.0);
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        static EPOCH: LazyLock<std::time::Instant> = LazyLock::new(std::time::Instant::now);
+        EPOCH.elapsed().as_secs_f64()
+    }
+}
+
+/// Number of errors kept in [`Context::error_log`]
+pub const ERROR_LOG_SIZE: usize = 20;
 
 #[derive(Default)]
 pub struct Renderer {
@@ -36,15 +146,63 @@ pub struct Renderer {
 }
 
 impl Renderer {
-    pub fn frame(
-        &self,
-        vm: &VirtualMachine,
-        camera: &mut Camera,
-        assets: &Assets,
-        screen_space: Rect,
-    ) -> PyResult<()> {
-        let mut queue = self.render_queue.lock().unwrap();
-        for instruction in queue.drain(..) {
+    /// Upload new and changed images, which has to happen before [`Renderer::frame`]
+    pub fn upload_images(&mut self, graphics: &mut Graphics2D, context: &Context) {
+        let mut upload = |size: UVec2, data: &[u8]| {
+            graphics
+                .create_image_from_raw_pixels(
+                    speedy2d::image::ImageDataType::RGBA,
+                    speedy2d::image::ImageSmoothingMode::NearestNeighbor,
+                    size,
+                    data,
+                )
+                .unwrap()
+        };
+        let mut canvases = context.canvases.lock().unwrap();
+        for image in context.new_images.lock().unwrap().drain(..) {
+            let handle = match image {
+                Some((size, data)) => upload(size, &data),
+                None => {
+                    let canvas = canvases.get_mut(&self.image_map.len()).unwrap();
+                    canvas.dirty = false;
+                    upload(canvas.size, &canvas.pixels)
+                }
+            };
+            self.image_map.push(handle);
+        }
+        // speedy2d can't write into an existing image, so a changed canvas gets a new one,
+        // which frees the old one
+        for (&image, canvas) in canvases.iter_mut().filter(|(_, canvas)| canvas.dirty) {
+            if let Some(handle) = self.image_map.get_mut(image) {
+                *handle = upload(canvas.size, &canvas.pixels);
+                canvas.dirty = false;
+            }
+        }
+    }
+
+    /// Draw what the app queued. Image handles were checked when they were drawn, and every
+    /// image is uploaded by [`Renderer::upload_images`] first
+    pub fn frame(&self, camera: &mut Camera, screen_space: Rect) {
+        let scale = camera.scale;
+        let to_screen = |position: Vec2| (position + screen_space.top_left()) * scale;
+        let set_clip = |camera: &mut Camera, clip: &Rect| {
+            use speedy2d::numeric::RoundFloat;
+            camera
+                .graphics
+                .set_clip(Some(speedy2d::shape::Rectangle::new(
+                    clip.top_left().round().into_i32(),
+                    clip.bottom_right().round().into_i32(),
+                )))
+        };
+        // Apps never draw outside of the watch screen
+        let mut clips = vec![Rect::new(
+            to_screen(Vec2::ZERO),
+            to_screen(screen_space.size()),
+        )];
+        set_clip(camera, &clips[0]);
+
+        let queue = self.render_queue.lock().unwrap();
+        for instruction in queue.iter().cloned() {
             match instruction {
                 pywatch::PyRenderInstruction::Image {
                     image,
@@ -52,10 +210,7 @@ impl Renderer {
                     size,
                     uv,
                 } => {
-                    let image = self
-                        .image_map
-                        .get(image)
-                        .ok_or_else(|| vm.new_value_error("Invalid image".to_owned()))?;
+                    let image = &self.image_map[image];
                     position += screen_space.top_left();
                     position *= camera.scale;
                     let mut size = size.unwrap_or(image.size().into_f32());
@@ -74,145 +229,318 @@ impl Renderer {
                         image,
                     )
                 }
+                pywatch::PyRenderInstruction::Sprite {
+                    image,
+                    position,
+                    size,
+                    uv,
+                    tint,
+                    rotation,
+                    origin,
+                    flip_h,
+                    flip_v,
+                } => {
+                    let image = &self.image_map[image];
+                    let (mut uv_tl, mut uv_br) = uv.unwrap_or((Vec2::ZERO, Vec2::new(1.0, 1.0)));
+                    let size = size.unwrap_or_else(|| {
+                        let size = image.size().into_f32();
+                        Vec2::new(size.x * (uv_br.x - uv_tl.x), size.y * (uv_br.y - uv_tl.y))
+                    });
+                    if flip_h {
+                        std::mem::swap(&mut uv_tl.x, &mut uv_br.x);
+                    }
+                    if flip_v {
+                        std::mem::swap(&mut uv_tl.y, &mut uv_br.y);
+                    }
+                    let (sin, cos) = rotation.sin_cos();
+                    let corner = |corner: Vec2| {
+                        let corner = corner - origin;
+                        to_screen(
+                            position
+                                + Vec2::new(
+                                    corner.x * cos - corner.y * sin,
+                                    corner.x * sin + corner.y * cos,
+                                ),
+                        )
+                    };
+                    camera.graphics.draw_quad_image_tinted_four_color(
+                        [
+                            corner(Vec2::ZERO),
+                            corner(Vec2::new(size.x, 0.0)),
+                            corner(size),
+                            corner(Vec2::new(0.0, size.y)),
+                        ],
+                        [tint; 4],
+                        [
+                            uv_tl,
+                            Vec2::new(uv_br.x, uv_tl.y),
+                            uv_br,
+                            Vec2::new(uv_tl.x, uv_br.y),
+                        ],
+                        image,
+                    )
+                }
                 pywatch::PyRenderInstruction::Text {
                     text,
-                    mut position,
-                    mut size,
+                    position,
+                    size,
                     color,
+                    align,
+                    wrap_width,
                 } => {
-                    position += screen_space.top_left();
-                    position *= camera.scale;
-                    size *= camera.scale;
-                    camera.graphics.draw_text(
-                        position,
-                        color,
-                        &assets.font.layout_text(
-                            &text,
-                            size,
-                            speedy2d::font::TextOptions::default().with_wrap_to_width(
-                                screen_space.width() * camera.scale,
-                                speedy2d::font::TextAlignment::Left,
-                            ),
+                    let wrap_width = wrap_width.unwrap_or(watch::SCREEN_SIZE.x);
+                    let layout = super::text::LAYOUTS.lock().unwrap().layout(
+                        &text,
+                        size * scale,
+                        Some(wrap_width * scale),
+                        align,
+                    );
+                    camera
+                        .graphics
+                        .draw_text(to_screen(position), color, &layout)
+                }
+                pywatch::PyRenderInstruction::Rect {
+                    position,
+                    size,
+                    thickness,
+                    color,
+                } => {
+                    let tl = to_screen(position);
+                    let br = to_screen(position + size);
+                    let thickness = (thickness * scale).min((br.x - tl.x).min(br.y - tl.y) / 2.0);
+                    for side in [
+                        Rect::new(tl, Vec2::new(br.x, tl.y + thickness)),
+                        Rect::new(Vec2::new(tl.x, br.y - thickness), br),
+                        Rect::new(
+                            Vec2::new(tl.x, tl.y + thickness),
+                            Vec2::new(tl.x + thickness, br.y - thickness),
                         ),
-                    )
+                        Rect::new(
+                            Vec2::new(br.x - thickness, tl.y + thickness),
+                            Vec2::new(br.x, br.y - thickness),
+                        ),
+                    ] {
+                        camera.graphics.draw_rectangle(side, color);
+                    }
+                }
+                pywatch::PyRenderInstruction::FilledRect {
+                    position,
+                    size,
+                    color,
+                } => camera.graphics.draw_rectangle(
+                    Rect::new(to_screen(position), to_screen(position + size)),
+                    color,
+                ),
+                pywatch::PyRenderInstruction::RoundedRect {
+                    position,
+                    size,
+                    radius,
+                    color,
+                } => camera.graphics.draw_rounded_rectangle(
+                    speedy2d::shape::RoundRect::new(
+                        to_screen(position),
+                        to_screen(position + size),
+                        radius.min(size.x.min(size.y) / 2.0) * scale,
+                    ),
+                    color,
+                ),
+                pywatch::PyRenderInstruction::Line {
+                    start,
+                    end,
+                    thickness,
+                    color,
+                } => camera.graphics.draw_line(
+                    to_screen(start),
+                    to_screen(end),
+                    thickness * scale,
+                    color,
+                ),
+                pywatch::PyRenderInstruction::Circle {
+                    center,
+                    radius,
+                    color,
+                } => camera
+                    .graphics
+                    .draw_circle(to_screen(center), radius * scale, color),
+                pywatch::PyRenderInstruction::PushClip { position, size } => {
+                    let clip = Rect::new(to_screen(position), to_screen(position + size));
+                    let parent = clips.last().unwrap();
+                    let clip = clip
+                        .intersect(parent)
+                        .unwrap_or(Rect::new(*parent.top_left(), *parent.top_left()));
+                    set_clip(camera, &clip);
+                    clips.push(clip);
+                }
+                pywatch::PyRenderInstruction::PopClip => {
+                    if clips.len() > 1 {
+                        clips.pop();
+                        set_clip(camera, clips.last().unwrap());
+                    }
                 }
             }
         }
-
-        Ok(())
+        camera.graphics.set_clip(None);
     }
 }
 
 impl super::Interpreter {
+    /// Process actions queued by Python. Returns true if tiles of the level were changed
     pub fn update_queue(
         &mut self,
-        graphics: &mut Graphics2D,
+        rules: &rules::LevelRules,
         level: &mut world::Level,
+        tiles: &tiles::TileTable,
         player: &Player,
         apps: &mut Vec<App>,
-    ) {
-        let queue = std::mem::take(&mut ACTION_QUEUE.lock().unwrap().queue);
-
-        fn range_rect(origin: Vec2, size: Vec2, grid_size: UVec2) -> (IVec2, IVec2) {
-            let tl = origin - size / 2.0;
-            let tl = IVec2::new(
-                (tl.x / grid_size.x as f32).floor() as _,
-                (tl.y / grid_size.y as f32).floor() as _,
-            );
-            let br = origin + size / 2.0;
-            let br = IVec2::new(
-                (br.x / grid_size.x as f32).ceil() as _,
-                (br.y / grid_size.y as f32).ceil() as _,
-            );
-            (tl, br)
-        }
+    ) -> bool {
+        let queue = std::mem::take(&mut self.context.action_queue.lock().unwrap().queue);
+        let mut tiles_changed = false;
+        self.recorded_runs.clear();
+        let mut replayed_runs = self
+            .replayed_runs
+            .take()
+            .map(|runs| runs.into_iter().peekable());
+        // Actions other than runs processed so far, where a run is in the queue
+        let mut position = 0;
 
         for action in queue {
+            if !matches!(action, Action::Run(_)) {
+                if let Some(runs) = &mut replayed_runs {
+                    while let Some(run) = runs.next_if(|run| run.position <= position) {
+                        self.run_from_app(&run.code);
+                    }
+                }
+                position += 1;
+            }
             match action {
-                Action::LoadImage(image) => {
-                    let image = graphics
-                        .create_image_from_file_bytes(
-                            None,
-                            speedy2d::image::ImageSmoothingMode::NearestNeighbor,
-                            std::io::Cursor::new(image),
-                        )
-                        .unwrap();
-                    IMAGE_SIZE.lock().unwrap().push(image.size().into_f32());
-                    self.renderer.image_map.push(image);
+                Action::LoadSound(sound) => self.sounds.push(sound),
+                Action::PlaySound(sound, volume) => {
+                    if let Some(sound) = self.sounds.get(sound) {
+                        self.played_sounds.push((sound.clone(), volume));
+                    }
                 }
-                Action::UnlockNearest => {
+                Action::PlayTone(tone) => {
+                    self.played_sounds.push((Arc::new(tone.render()), 1.0));
+                }
+                Action::UnlockNearest | Action::LockNearest => {
+                    let locked = matches!(action, Action::LockNearest);
                     let origin = player.position + player.size.into_f32() / 2.0;
-                    let area = Vec2::new(80.0, 80.0);
-                    let (tl, br) = range_rect(origin, area, level.foreground.grid_size());
-                    for y in tl.y..br.y {
-                        for x in tl.x..br.x {
-                            let position = IVec2::new(x, y);
-                            if let Some(tile) = level.foreground.get(position) {
-                                if tile.position == UVec2::new(7, 3) {
-                                    level.foreground.get_mut(position).unwrap().position.x += 1;
-                                    level
-                                        .foreground
-                                        .get_mut(position - IVec2::new_y(1))
-                                        .unwrap()
-                                        .position
-                                        .x += 1;
-                                }
-                            }
+                    let mut changed = false;
+                    let grid_size = level.foreground.grid_size().into_f32();
+                    for door in tiles.doors(level) {
+                        if door.in_reach(origin, rules.door_radius, grid_size) {
+                            changed |= self.set_door(level, tiles, &door, locked);
                         }
                     }
+                    if changed {
+                        self.effects.push(if locked {
+                            audio::Effect::DoorLock
+                        } else {
+                            audio::Effect::DoorUnlock
+                        });
+                    }
+                    tiles_changed |= changed;
                 }
-                Action::LockNearest => {
+                Action::SetDoor(id, locked) => {
                     let origin = player.position + player.size.into_f32() / 2.0;
-                    let area = Vec2::new(80.0, 80.0);
-                    let (tl, br) = range_rect(origin, area, level.foreground.grid_size());
-                    for y in tl.y..br.y {
-                        for x in tl.x..br.x {
-                            let position = IVec2::new(x, y);
-                            if let Some(tile) = level.foreground.get(position) {
-                                if tile.position == UVec2::new(8, 3) {
-                                    level.foreground.get_mut(position).unwrap().position.x -= 1;
-                                    level
-                                        .foreground
-                                        .get_mut(position - IVec2::new_y(1))
-                                        .unwrap()
-                                        .position
-                                        .x -= 1;
-                                }
+                    let grid_size = level.foreground.grid_size().into_f32();
+                    let door = tiles.doors(level).into_iter().find(|door| door.id == id);
+                    match door {
+                        Some(door) if door.in_reach(origin, rules.door_radius, grid_size) => {
+                            if self.set_door(level, tiles, &door, locked) {
+                                self.effects.push(if locked {
+                                    audio::Effect::DoorLock
+                                } else {
+                                    audio::Effect::DoorUnlock
+                                });
+                                tiles_changed = true;
                             }
                         }
+                        Some(_) => log_error(&format!("Door {} is out of reach", id)),
+                        None => log_error(&format!("No door with id {}", id)),
                     }
                 }
                 Action::Run(code) => {
-                    *CAPTURE_OUTPUT.lock().unwrap() = Some(String::new());
-                    self.enter(|vm| {
-                        vm.run_code_string(self.player_scope.clone(), &code, "<stdin>".to_owned())
-                    });
-                    let output = CAPTURE_OUTPUT.lock().unwrap().take().unwrap();
-                    self.enter(|vm| {
-                        if let Some(module) = &self.current_app {
-                            let handler = module.get_attr("on_run_output", vm)?;
-                            handler.call((output,), vm).map(|_| ())
-                        } else {
-                            Ok(())
-                        }
-                    });
+                    if replayed_runs.is_none() {
+                        self.run_from_app(&code);
+                        self.recorded_runs
+                            .push(replay::ReplayRun { position, code });
+                    }
+                }
+                Action::AddApp(module, source) => {
+                    if apps.iter().any(|app| app.module == module) {
+                        log_error(&format!("App {} is already added", module));
+                    } else if self.install_app(&module, source.as_deref()) {
+                        let mut app =
+                            App::new(UVec2::new(4, 0), Box::leak(Box::new(module)).as_str());
+                        app.source = source;
+                        apps.push(app);
+                    }
+                }
+                Action::SetBackground(module, background) => {
+                    match apps.iter_mut().find(|app| app.module == module) {
+                        Some(app) => app.background = background,
+                        None => log_error(&format!("No app named {}", module)),
+                    }
                 }
-                Action::AddApp(module) => {
-                    apps.push(App::new(
-                        UVec2::new(4, 0),
-                        Box::leak(Box::new(module)).as_str(),
-                    ));
+                Action::SetWeather(weather) => {
+                    if weather != self.weather {
+                        self.weather = weather;
+                        self.events.push(events::Event::WeatherChanged(weather));
+                    }
                 }
             }
         }
+
+        for run in replayed_runs.into_iter().flatten() {
+            self.run_from_app(&run.code);
+        }
+        tiles_changed
+    }
+
+    /// Lock or unlock a door and raise its event. Returns true if it changed
+    fn set_door(
+        &mut self,
+        level: &mut world::Level,
+        tiles: &tiles::TileTable,
+        door: &tiles::Door,
+        locked: bool,
+    ) -> bool {
+        if !tiles.set_door(level, door.bottom, locked) {
+            return false;
+        }
+        self.events.push(if locked {
+            events::Event::DoorLocked(door.id, door.center)
+        } else {
+            events::Event::DoorUnlocked(door.id, door.center)
+        });
+        true
+    }
+
+    /// Run code like `watch.run` does, passing the output to the current app
+    fn run_from_app(&mut self, code: &str) {
+        let output = self.run(code);
+        *self.context.run_failed.lock().unwrap() = output.is_err();
+        let output = output.unwrap_or_else(|output| output);
+        self.enter(|vm| {
+            if let Some(module) = &self.current_app {
+                let handler = module.get_attr("on_run_output", vm)?;
+                handler.call((output,), vm).map(|_| ())
+            } else {
+                Ok(())
+            }
+        });
     }
 }
 
 // * Py data types
 pub type PyImage = usize;
+pub type PySound = usize;
+pub type PyTimer = usize;
+pub type PyListener = usize;
 
 #[derive(Clone, Debug)]
-pub struct PyVec2(Vec2);
+pub struct PyVec2(pub Vec2);
 
 impl ToPyObject for PyVec2 {
     fn to_pyobject(self, vm: &VirtualMachine) -> PyObjectRef {
@@ -235,6 +563,50 @@ impl TryFromBorrowedObject<'_> for PyVec2 {
 }
 
 // * Frame
+#[derive(FromArgs)]
+pub struct SpriteArgs {
+    #[pyarg(any)]
+    image: PyImage,
+    #[pyarg(any)]
+    position: PyVec2,
+    #[pyarg(any, default)]
+    size: Option<PyVec2>,
+    /// Tuple of top left and bottom right, normalized
+    #[pyarg(any, default)]
+    uv: Option<builtins::PyTupleRef>,
+    /// `0xRRGGBB` like every other color, its transparency is `alpha`
+    #[pyarg(any, default = "0xFFFFFF")]
+    tint: u32,
+    #[pyarg(any, optional)]
+    alpha: OptionalArg<ArgIntoFloat>,
+    #[pyarg(any, optional)]
+    rotation: OptionalArg<ArgIntoFloat>,
+    #[pyarg(any, default)]
+    origin: Option<PyVec2>,
+    #[pyarg(any, default = "false")]
+    flip_h: bool,
+    #[pyarg(any, default = "false")]
+    flip_v: bool,
+}
+
+#[derive(FromArgs)]
+pub struct TextArgs {
+    #[pyarg(any)]
+    position: PyVec2,
+    #[pyarg(any)]
+    text: String,
+    #[pyarg(any)]
+    size: ArgIntoFloat,
+    #[pyarg(any)]
+    color: u32,
+    #[pyarg(any, optional)]
+    align: OptionalArg<String>,
+    #[pyarg(any, default)]
+    wrap_width: Option<ArgIntoFloat>,
+    #[pyarg(any, optional)]
+    alpha: OptionalArg<ArgIntoFloat>,
+}
+
 #[pyattr]
 #[pyclass(module = "watch", name)]
 #[derive(Debug, PyPayload)]
@@ -314,16 +686,19 @@ impl Frame {
     }
 
     #[pymethod]
-    pub fn draw_image(&self, position: PyVec2, image: PyImage) {
-        self.render_queue
-            .lock()
-            .unwrap()
-            .push(PyRenderInstruction::Image {
-                image,
-                position: position.0,
-                size: None,
-                uv: None,
-            });
+    pub fn draw_image(
+        &self,
+        position: PyVec2,
+        image: PyImage,
+        vm: &VirtualMachine,
+    ) -> PyResult<()> {
+        self.draw(PyRenderInstruction::Image {
+            image: check_image(image, vm)?,
+            position: position.0,
+            size: None,
+            uv: None,
+        });
+        Ok(())
     }
 
     #[pymethod]
@@ -334,34 +709,207 @@ impl Frame {
         size: Option<PyVec2>,
         uv_tl: PyVec2,
         uv_br: PyVec2,
-    ) {
-        self.render_queue
-            .lock()
-            .unwrap()
-            .push(PyRenderInstruction::Image {
-                image,
-                position: position.0,
-                size: size.map(|size| size.0),
-                uv: Some((uv_tl.0, uv_br.0)),
-            });
+        vm: &VirtualMachine,
+    ) -> PyResult<()> {
+        self.draw(PyRenderInstruction::Image {
+            image: check_image(image, vm)?,
+            position: position.0,
+            size: size.map(|size| size.0),
+            uv: Some((uv_tl.0, uv_br.0)),
+        });
+        Ok(())
+    }
+
+    #[pymethod]
+    pub fn draw_text(&self, args: TextArgs, vm: &VirtualMachine) -> PyResult<()> {
+        self.draw(PyRenderInstruction::Text {
+            text: args.text,
+            position: args.position.0,
+            size: *args.size as f32,
+            color: color(args.color, args.alpha, vm)?,
+            align: text_align(args.align, vm)?,
+            wrap_width: args.wrap_width.map(|width| *width as f32),
+        });
+        Ok(())
     }
 
     #[pymethod]
-    pub fn draw_text(&self, position: PyVec2, text: String, size: f32, color: u32) {
-        self.render_queue
-            .lock()
-            .unwrap()
-            .push(PyRenderInstruction::Text {
-                text,
-                position: position.0,
-                size,
-                color: Color::from_hex_rgb(color),
-            });
+    pub fn draw_sprite(&self, args: SpriteArgs, vm: &VirtualMachine) -> PyResult<()> {
+        let uv = match args.uv.as_ref().map(|uv| uv.as_slice()) {
+            None => None,
+            Some([tl, br]) => Some((
+                PyVec2::try_from_borrowed_object(vm, tl)?.0,
+                PyVec2::try_from_borrowed_object(vm, br)?.0,
+            )),
+            Some(_) => {
+                return Err(vm.new_value_error(
+                    "uv has to be a tuple of top left and bottom right".to_owned(),
+                ))
+            }
+        };
+        self.draw(PyRenderInstruction::Sprite {
+            image: check_image(args.image, vm)?,
+            position: args.position.0,
+            size: args.size.map(|size| size.0),
+            uv,
+            tint: color(args.tint, args.alpha, vm)?,
+            rotation: args.rotation.map_or(0.0, |rotation| *rotation as f32),
+            origin: args.origin.map_or(Vec2::ZERO, |origin| origin.0),
+            flip_h: args.flip_h,
+            flip_v: args.flip_v,
+        });
+        Ok(())
+    }
+
+    fn draw(&self, instruction: PyRenderInstruction) {
+        self.render_queue.lock().unwrap().push(instruction);
+    }
+
+    #[pymethod]
+    pub fn draw_rect(
+        &self,
+        position: PyVec2,
+        size: PyVec2,
+        rgb: u32,
+        thickness: OptionalArg<ArgIntoFloat>,
+        alpha: OptionalArg<ArgIntoFloat>,
+        vm: &VirtualMachine,
+    ) -> PyResult<()> {
+        self.draw(PyRenderInstruction::Rect {
+            position: position.0,
+            size: size.0,
+            thickness: thickness.map_or(1.0, |thickness| *thickness as f32),
+            color: color(rgb, alpha, vm)?,
+        });
+        Ok(())
+    }
+
+    #[pymethod]
+    pub fn fill_rect(
+        &self,
+        position: PyVec2,
+        size: PyVec2,
+        rgb: u32,
+        alpha: OptionalArg<ArgIntoFloat>,
+        vm: &VirtualMachine,
+    ) -> PyResult<()> {
+        self.draw(PyRenderInstruction::FilledRect {
+            position: position.0,
+            size: size.0,
+            color: color(rgb, alpha, vm)?,
+        });
+        Ok(())
+    }
+
+    #[pymethod]
+    pub fn draw_rounded_rect(
+        &self,
+        position: PyVec2,
+        size: PyVec2,
+        radius: ArgIntoFloat,
+        rgb: u32,
+        alpha: OptionalArg<ArgIntoFloat>,
+        vm: &VirtualMachine,
+    ) -> PyResult<()> {
+        self.draw(PyRenderInstruction::RoundedRect {
+            position: position.0,
+            size: size.0,
+            radius: *radius as f32,
+            color: color(rgb, alpha, vm)?,
+        });
+        Ok(())
+    }
+
+    #[pymethod]
+    pub fn draw_line(
+        &self,
+        start: PyVec2,
+        end: PyVec2,
+        rgb: u32,
+        thickness: OptionalArg<ArgIntoFloat>,
+        alpha: OptionalArg<ArgIntoFloat>,
+        vm: &VirtualMachine,
+    ) -> PyResult<()> {
+        self.draw(PyRenderInstruction::Line {
+            start: start.0,
+            end: end.0,
+            thickness: thickness.map_or(1.0, |thickness| *thickness as f32),
+            color: color(rgb, alpha, vm)?,
+        });
+        Ok(())
+    }
+
+    #[pymethod]
+    pub fn push_clip(&self, tl: PyVec2, size: PyVec2) {
+        self.draw(PyRenderInstruction::PushClip {
+            position: tl.0,
+            size: size.0,
+        });
+    }
+
+    #[pymethod]
+    pub fn pop_clip(&self) {
+        self.draw(PyRenderInstruction::PopClip);
+    }
+
+    #[pymethod]
+    pub fn draw_circle(
+        &self,
+        center: PyVec2,
+        radius: ArgIntoFloat,
+        rgb: u32,
+        alpha: OptionalArg<ArgIntoFloat>,
+        vm: &VirtualMachine,
+    ) -> PyResult<()> {
+        self.draw(PyRenderInstruction::Circle {
+            center: center.0,
+            radius: *radius as f32,
+            color: color(rgb, alpha, vm)?,
+        });
+        Ok(())
     }
 }
 
+#[derive(FromArgs)]
+pub struct MeasureTextArgs {
+    #[pyarg(any)]
+    text: String,
+    #[pyarg(any)]
+    size: ArgIntoFloat,
+    #[pyarg(any, optional)]
+    align: OptionalArg<String>,
+    #[pyarg(any, default)]
+    wrap_width: Option<ArgIntoFloat>,
+}
+
+/// Left unless given
+fn text_align(align: OptionalArg<String>, vm: &VirtualMachine) -> PyResult<super::text::Align> {
+    match align.as_option() {
+        Some(align) => super::text::Align::parse(align, vm),
+        None => Ok(super::text::Align::Left),
+    }
+}
+
+/// Size of text as `Frame.draw_text` would draw it, with the same defaults
+#[pyfunction]
+pub fn measure_text(args: MeasureTextArgs, vm: &VirtualMachine) -> PyResult<PyVec2> {
+    let wrap_width = args
+        .wrap_width
+        .map_or(watch::SCREEN_SIZE.x, |width| *width as f32);
+    let layout = super::text::LAYOUTS.lock().unwrap().layout(
+        &args.text,
+        *args.size as f32,
+        Some(wrap_width),
+        text_align(args.align, vm)?,
+    );
+    Ok(PyVec2(layout.size()))
+}
+
 // * Weather
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum Weather {
     #[default]
     Sunny,
@@ -369,29 +917,43 @@ pub enum Weather {
     Snowy,
 }
 
-pub static WEATHER: LazyLock<Arc<Mutex<Weather>>> =
-    LazyLock::new(|| Arc::new(Mutex::new(Weather::default())));
 #[pyfunction]
 pub fn set_weather(weather_in: String) {
-    let mut weather = WEATHER.lock().unwrap();
-    match weather_in.as_str() {
-        "sunny" => *weather = Weather::Sunny,
-        "rainy" => *weather = Weather::Rainy,
-        "snowy" => *weather = Weather::Snowy,
-        _ => (),
+    let weather = match weather_in.as_str() {
+        "sunny" => Weather::Sunny,
+        "rainy" => Weather::Rainy,
+        "snowy" => Weather::Snowy,
+        _ => return,
     };
+    context().queue(Action::SetWeather(weather));
 }
 
 // * Interpreter
 #[pyfunction]
 pub fn run(code: String) {
-    let mut queue = ACTION_QUEUE.lock().unwrap();
-    queue.queue.push(Action::Run(code));
+    context().queue(Action::Run(code));
+}
+
+#[pyfunction]
+pub fn run_failed() -> bool {
+    *context().run_failed.lock().unwrap()
+}
+
+/// Last exceptions raised by apps, the terminal or any other Python code, oldest first
+#[pyfunction]
+pub fn errors(vm: &VirtualMachine) -> builtins::PyListRef {
+    let context = context();
+    let log = context.error_log.lock().unwrap();
+    vm.ctx.new_list(
+        log.iter()
+            .map(|message| vm.ctx.new_str(message.as_str()).into())
+            .collect(),
+    )
 }
 
 #[pyfunction]
 pub fn print(message: String) {
-    if let Some(output) = &mut *CAPTURE_OUTPUT.lock().unwrap() {
+    if let Some(output) = &mut *context().capture_output.lock().unwrap() {
         output.push_str(&message);
     } else {
         println!("{}", message);
@@ -401,35 +963,455 @@ pub fn print(message: String) {
 // * MISC
 #[pyfunction]
 pub fn lock_nearest() {
-    let mut queue = ACTION_QUEUE.lock().unwrap();
-    queue.queue.push(Action::LockNearest);
+    context().queue(Action::LockNearest);
 }
 
 #[pyfunction]
 pub fn unlock_nearest() {
-    let mut queue = ACTION_QUEUE.lock().unwrap();
-    queue.queue.push(Action::UnlockNearest);
+    context().queue(Action::UnlockNearest);
+}
+
+#[pyfunction]
+pub fn set_door(id: usize, locked: bool, vm: &VirtualMachine) -> PyResult<()> {
+    with_state(vm, |state| state.reachable_door(id, vm))?;
+    context().queue(Action::SetDoor(id, locked));
+    Ok(())
+}
+
+#[pyfunction]
+pub fn add_app(module: String, source: OptionalArg<String>) {
+    context().queue(Action::AddApp(module, source.into_option()));
+}
+
+#[pyfunction]
+pub fn set_background(module: String, enabled: bool) {
+    context().queue(Action::SetBackground(module, enabled));
+}
+
+/// Dictionary shared between all apps and the terminal, as apps can't see each other's globals
+#[pyattr]
+fn shared(vm: &VirtualMachine) -> builtins::PyDictRef {
+    vm.ctx.new_dict()
+}
+
+// * World
+fn with_state<R>(
+    vm: &VirtualMachine,
+    f: impl FnOnce(&super::state::State) -> PyResult<R>,
+) -> PyResult<R> {
+    match &*context().state.lock().unwrap() {
+        Some(state) => f(state),
+        None => Err(vm.new_runtime_error("The game has not started yet".to_owned())),
+    }
+}
+
+#[pyfunction]
+pub fn player(vm: &VirtualMachine) -> PyResult<builtins::PyDictRef> {
+    with_state(vm, |state| state.player(vm))
+}
+
+#[pyfunction]
+pub fn level(vm: &VirtualMachine) -> PyResult<builtins::PyDictRef> {
+    with_state(vm, |state| state.level(vm))
+}
+
+#[pyfunction]
+pub fn doors(vm: &VirtualMachine) -> PyResult<builtins::PyListRef> {
+    with_state(vm, |state| state.doors(vm))
+}
+
+#[pyfunction]
+pub fn tile(layer: String, x: i32, y: i32, vm: &VirtualMachine) -> PyResult<PyObjectRef> {
+    with_state(vm, |state| state.tile(&layer, x, y, vm))
+}
+
+#[pyfunction]
+pub fn raycast(
+    origin: PyVec2,
+    direction: PyVec2,
+    max_distance: ArgIntoFloat,
+    vm: &VirtualMachine,
+) -> PyResult<PyObjectRef> {
+    with_state(vm, |state| {
+        state.raycast(origin.0, direction.0, *max_distance as f32, vm)
+    })
+}
+
+#[pyfunction]
+pub fn overlap_rect(
+    tl: PyVec2,
+    size: PyVec2,
+    vm: &VirtualMachine,
+) -> PyResult<builtins::PyDictRef> {
+    with_state(vm, |state| state.overlap_rect(tl.0, size.0, vm))
+}
+
+// * Time
+#[derive(FromArgs)]
+pub struct TimeArgs {
+    #[pyarg(any, default = "false")]
+    real: bool,
+}
+
+#[pyfunction]
+pub fn time(args: TimeArgs) -> f64 {
+    let context = context();
+    let clock = context.clock.lock().unwrap();
+    if args.real {
+        clock.real
+    } else {
+        clock.game
+    }
+}
+
+/// Scheduled calls by [`PyTimer`], as `(game time it's due, interval or None, callable)`.
+/// They are ran by [`super::Interpreter::update_timers`]
+#[pyattr]
+fn _timers(vm: &VirtualMachine) -> builtins::PyDictRef {
+    vm.ctx.new_dict()
+}
+
+pub fn timers(vm: &VirtualMachine) -> PyResult<builtins::PyDictRef> {
+    vm.import("watch", 0)?
+        .get_attr("_timers", vm)?
+        .downcast()
+        .map_err(|_| vm.new_type_error("watch._timers has to be a dict".to_owned()))
+}
+
+fn schedule(
+    seconds: f64,
+    interval: Option<f64>,
+    callable: PyObjectRef,
+    vm: &VirtualMachine,
+) -> PyResult<PyTimer> {
+    if !callable.is_callable() {
+        return Err(vm.new_type_error(format!(
+            "'{}' object is not callable",
+            callable.class().name()
+        )));
+    }
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(vm.new_value_error("Time has to be a positive number".to_owned()));
+    }
+    let (due, timer) = {
+        let context = context();
+        let mut clock = context.clock.lock().unwrap();
+        clock.next_timer += 1;
+        (clock.game + seconds, clock.next_timer - 1)
+    };
+    let entry = vm.new_tuple((due, interval, callable));
+    timers(vm)?.set_item(&timer, entry.into(), vm)?;
+    Ok(timer)
+}
+
+#[pyfunction]
+pub fn after(
+    seconds: ArgIntoFloat,
+    callable: PyObjectRef,
+    vm: &VirtualMachine,
+) -> PyResult<PyTimer> {
+    schedule(*seconds, None, callable, vm)
+}
+
+#[pyfunction]
+pub fn every(
+    seconds: ArgIntoFloat,
+    callable: PyObjectRef,
+    vm: &VirtualMachine,
+) -> PyResult<PyTimer> {
+    if *seconds <= 0.0 {
+        return Err(vm.new_value_error("Interval has to be more than 0".to_owned()));
+    }
+    schedule(*seconds, Some(*seconds), callable, vm)
+}
+
+/// Returns false if the timer already went off or was canceled
+#[pyfunction]
+pub fn cancel(timer: PyTimer, vm: &VirtualMachine) -> PyResult<bool> {
+    let timers = timers(vm)?;
+    if timers.get_item_opt(&timer, vm)?.is_none() {
+        return Ok(false);
+    }
+    timers.del_item(&timer, vm)?;
+    Ok(true)
+}
+
+// * Events
+/// Listeners by [`PyListener`], as `(event name, callable)`. They are called by [`super::Interpreter::dispatch_events`]
+#[pyattr]
+fn _listeners(vm: &VirtualMachine) -> builtins::PyDictRef {
+    vm.ctx.new_dict()
+}
+
+pub fn listeners(vm: &VirtualMachine) -> PyResult<builtins::PyDictRef> {
+    vm.import("watch", 0)?
+        .get_attr("_listeners", vm)?
+        .downcast()
+        .map_err(|_| vm.new_type_error("watch._listeners has to be a dict".to_owned()))
+}
+
+#[pyfunction]
+pub fn on(event: String, callable: PyObjectRef, vm: &VirtualMachine) -> PyResult<PyListener> {
+    if !super::events::Event::NAMES.contains(&event.as_str()) {
+        return Err(vm.new_value_error(format!("Invalid event: {:?}", event)));
+    }
+    if !callable.is_callable() {
+        return Err(vm.new_type_error(format!(
+            "'{}' object is not callable",
+            callable.class().name()
+        )));
+    }
+    let listener = {
+        let context = context();
+        let mut next = context.next_listener.lock().unwrap();
+        *next += 1;
+        *next - 1
+    };
+    let entry = vm.new_tuple((event, callable));
+    listeners(vm)?.set_item(&listener, entry.into(), vm)?;
+    Ok(listener)
+}
+
+/// Returns false if the listener was already removed
+#[pyfunction]
+pub fn off(listener: PyListener, vm: &VirtualMachine) -> PyResult<bool> {
+    let listeners = listeners(vm)?;
+    if listeners.get_item_opt(&listener, vm)?.is_none() {
+        return Ok(false);
+    }
+    listeners.del_item(&listener, vm)?;
+    Ok(true)
+}
+
+/// Give an image a handle, queueing it to be uploaded. Pixels of canvases are [`None`]
+fn add_image(size: UVec2, pixels: Option<Vec<u8>>) -> PyImage {
+    let context = context();
+    let mut image_size = context.image_size.lock().unwrap();
+    image_size.push(size.into_f32());
+    context
+        .new_images
+        .lock()
+        .unwrap()
+        .push(pixels.map(|pixels| (size, pixels)));
+    image_size.len() - 1
+}
+
+/// Raises ValueError if the handle isn't an image
+fn check_image(image: PyImage, vm: &VirtualMachine) -> PyResult<PyImage> {
+    if image < context().image_size.lock().unwrap().len() {
+        Ok(image)
+    } else {
+        Err(vm.new_value_error(format!("Invalid image: {}", image)))
+    }
 }
 
 #[pyfunction]
-pub fn add_app(module: String) {
-    let mut queue = ACTION_QUEUE.lock().unwrap();
-    queue.queue.push(Action::AddApp(module));
+pub fn load_image(data: Vec<u8>, vm: &VirtualMachine) -> PyResult<PyImage> {
+    let image = image::load_from_memory(&data)
+        .map_err(|err| vm.new_value_error(format!("Invalid image data: {}", err)))?
+        .into_rgba8();
+    let size = UVec2::new(image.width(), image.height());
+    Ok(add_image(size, Some(image.into_raw())))
 }
 
 #[pyfunction]
-pub fn load_image(data: Vec<u8>) -> PyImage {
-    let mut queue = ACTION_QUEUE.lock().unwrap();
-    queue.queue.push(Action::LoadImage(data));
-    queue.next_image_index += 1;
-    queue.next_image_index - 1
+pub fn image_size(image: PyImage, vm: &VirtualMachine) -> PyResult<PyVec2> {
+    match context().image_size.lock().unwrap().get(image) {
+        Some(size) => Ok(PyVec2(*size)),
+        None => Err(vm.new_value_error(format!("Invalid image: {}", image))),
+    }
 }
 
+/// Transparent image that can be drawn into with [`set_pixel`], [`fill`], [`blit`] and [`set_bytes`].
+/// Changes are uploaded before it's drawn
 #[pyfunction]
-pub fn image_size(image: PyImage) -> PyVec2 {
-    PyVec2(IMAGE_SIZE.lock().unwrap()[image])
-    // .get(image)
-    // .ok_or_else(|| vm.new_value_error("Invalid image".to_owned()))?;
+pub fn create_canvas(width: u32, height: u32, vm: &VirtualMachine) -> PyResult<PyImage> {
+    let max = super::canvas::MAX_CANVAS_SIZE;
+    if !(1..=max).contains(&width) || !(1..=max).contains(&height) {
+        return Err(vm.new_value_error(format!(
+            "Canvas size has to be between 1 and {}, got {}x{}",
+            max, width, height
+        )));
+    }
+    let size = UVec2::new(width, height);
+    let image = add_image(size, None);
+    context()
+        .canvases
+        .lock()
+        .unwrap()
+        .insert(image, super::canvas::Canvas::new(size));
+    Ok(image)
+}
+
+fn with_canvas<R>(
+    canvas: PyImage,
+    vm: &VirtualMachine,
+    f: impl FnOnce(&mut super::canvas::Canvas) -> R,
+) -> PyResult<R> {
+    match context().canvases.lock().unwrap().get_mut(&canvas) {
+        Some(canvas) => Ok(f(canvas)),
+        None => Err(vm.new_value_error(format!("Invalid canvas: {}", canvas))),
+    }
+}
+
+/// Pixel of a canvas from `0xRRGGBB` and an optional alpha, like [`color`]
+fn rgba(rgb: u32, alpha: OptionalArg<ArgIntoFloat>, vm: &VirtualMachine) -> PyResult<[u8; 4]> {
+    let color = color(rgb, alpha, vm)?;
+    let [_, r, g, b] = rgb.to_be_bytes();
+    Ok([r, g, b, (color.a() * 255.0).round() as u8])
+}
+
+#[pyfunction]
+pub fn set_pixel(
+    canvas: PyImage,
+    x: i32,
+    y: i32,
+    rgb: u32,
+    alpha: OptionalArg<ArgIntoFloat>,
+    vm: &VirtualMachine,
+) -> PyResult<()> {
+    let rgba = rgba(rgb, alpha, vm)?;
+    with_canvas(canvas, vm, |canvas| canvas.set_pixel(x, y, rgba))
+}
+
+/// `(0xRRGGBB, alpha)`, or None outside of the canvas
+#[pyfunction]
+pub fn get_pixel(canvas: PyImage, x: i32, y: i32, vm: &VirtualMachine) -> PyResult<PyObjectRef> {
+    let pixel = with_canvas(canvas, vm, |canvas| canvas.pixel(x, y))?;
+    Ok(match pixel {
+        Some([r, g, b, a]) => vm
+            .new_tuple((u32::from_be_bytes([0, r, g, b]), a as f64 / 255.0))
+            .into(),
+        None => vm.ctx.none(),
+    })
+}
+
+#[pyfunction]
+pub fn fill(
+    canvas: PyImage,
+    rgb: u32,
+    alpha: OptionalArg<ArgIntoFloat>,
+    vm: &VirtualMachine,
+) -> PyResult<()> {
+    let rgba = rgba(rgb, alpha, vm)?;
+    with_canvas(canvas, vm, |canvas| canvas.fill(rgba))
+}
+
+#[pyfunction]
+pub fn blit(
+    canvas: PyImage,
+    source: PyImage,
+    position: PyVec2,
+    vm: &VirtualMachine,
+) -> PyResult<()> {
+    if !position.0.x.is_finite() || !position.0.y.is_finite() {
+        return Err(vm.new_value_error("Position has to be finite".to_owned()));
+    }
+    let source = with_canvas(source, vm, |source| source.clone())?;
+    let position = IVec2::new(position.0.x.floor() as _, position.0.y.floor() as _);
+    with_canvas(canvas, vm, |canvas| canvas.blit(&source, position))
+}
+
+#[pyfunction]
+pub fn set_bytes(canvas: PyImage, data: Vec<u8>, vm: &VirtualMachine) -> PyResult<()> {
+    with_canvas(canvas, vm, |canvas| canvas.set_bytes(&data))?
+        .map_err(|err| vm.new_value_error(err))
+}
+
+// * Sound
+fn add_sound(sound: audio::Sound) -> PySound {
+    let context = context();
+    let mut queue = context.action_queue.lock().unwrap();
+    queue.queue.push(Action::LoadSound(Arc::new(sound)));
+    queue.next_sound_index += 1;
+    queue.next_sound_index - 1
+}
+
+#[pyfunction]
+pub fn load_sound(data: Vec<u8>, vm: &VirtualMachine) -> PyResult<PySound> {
+    let sound = audio::Sound::from_wav(&data).map_err(|err| vm.new_value_error(err.to_string()))?;
+    Ok(add_sound(sound))
+}
+
+#[derive(FromArgs)]
+pub struct ToneArgs {
+    #[pyarg(any)]
+    frequency: ArgIntoFloat,
+    #[pyarg(any)]
+    duration: ArgIntoFloat,
+    #[pyarg(any, optional)]
+    waveform: OptionalArg<String>,
+    #[pyarg(any, optional)]
+    end_frequency: OptionalArg<ArgIntoFloat>,
+    #[pyarg(any, optional)]
+    volume: OptionalArg<ArgIntoFloat>,
+}
+
+impl ToneArgs {
+    fn tone(self, vm: &VirtualMachine) -> PyResult<audio::Tone> {
+        let waveform = match self.waveform.as_option().map_or("square", String::as_str) {
+            "sine" => audio::Waveform::Sine,
+            "square" => audio::Waveform::Square,
+            "triangle" => audio::Waveform::Triangle,
+            "sawtooth" => audio::Waveform::Sawtooth,
+            "noise" => audio::Waveform::Noise,
+            waveform => return Err(vm.new_value_error(format!("Invalid waveform: {:?}", waveform))),
+        };
+        let duration = *self.duration as f32;
+        if !(0.0..=10.0).contains(&duration) {
+            return Err(
+                vm.new_value_error("Duration has to be between 0 and 10 seconds".to_owned())
+            );
+        }
+        let frequency = *self.frequency as f32;
+        let end_frequency = self.end_frequency.map_or(frequency, |end| *end as f32);
+        if ![frequency, end_frequency]
+            .iter()
+            .all(|frequency| frequency.is_finite() && *frequency > 0.0)
+        {
+            return Err(vm.new_value_error("Frequency has to be a positive number".to_owned()));
+        }
+        Ok(audio::Tone::new(waveform, frequency, duration)
+            .slide(end_frequency)
+            .volume(sound_volume(self.volume, vm)?))
+    }
+}
+
+/// 1 unless given
+fn sound_volume(volume: OptionalArg<ArgIntoFloat>, vm: &VirtualMachine) -> PyResult<f32> {
+    let volume = volume.map_or(1.0, |volume| *volume as f32);
+    if !volume.is_finite() || volume < 0.0 {
+        return Err(vm.new_value_error("Volume has to be a non-negative number".to_owned()));
+    }
+    Ok(volume)
+}
+
+#[pyfunction]
+pub fn tone(args: ToneArgs, vm: &VirtualMachine) -> PyResult<PySound> {
+    Ok(add_sound(audio::Sound::sequence(&[args.tone(vm)?])))
+}
+
+#[pyfunction]
+pub fn beep(args: ToneArgs, vm: &VirtualMachine) -> PyResult<()> {
+    let tone = args.tone(vm)?;
+    context().queue(Action::PlayTone(tone));
+    Ok(())
+}
+
+#[pyfunction]
+pub fn play_sound(
+    sound: PySound,
+    volume: OptionalArg<ArgIntoFloat>,
+    vm: &VirtualMachine,
+) -> PyResult<()> {
+    let volume = sound_volume(volume, vm)?;
+    let context = context();
+    let mut queue = context.action_queue.lock().unwrap();
+    if sound >= queue.next_sound_index {
+        return Err(vm.new_value_error(format!("Invalid sound: {}", sound)));
+    }
+    queue.queue.push(Action::PlaySound(sound, volume));
+    Ok(())
 }
 
 #[derive(Clone, Debug)]
@@ -440,12 +1422,79 @@ pub enum PyRenderInstruction {
         size: Option<Vec2>,
         uv: Option<(Vec2, Vec2)>,
     },
+    /// Image with a transform. Position is where the origin of the sprite goes
+    Sprite {
+        image: PyImage,
+        position: Vec2,
+        size: Option<Vec2>,
+        uv: Option<(Vec2, Vec2)>,
+        tint: Color,
+        /// In radians, clockwise around the origin
+        rotation: f32,
+        /// Relative to the top left corner of the sprite
+        origin: Vec2,
+        flip_h: bool,
+        flip_v: bool,
+    },
     Text {
         text: String,
         position: Vec2,
         size: f32,
         color: Color,
+        /// Of lines, within the wrap width
+        align: super::text::Align,
+        /// Width of the watch screen if None
+        wrap_width: Option<f32>,
     },
+    /// Outline of a rectangle, drawn inside of it
+    Rect {
+        position: Vec2,
+        size: Vec2,
+        thickness: f32,
+        color: Color,
+    },
+    FilledRect {
+        position: Vec2,
+        size: Vec2,
+        color: Color,
+    },
+    RoundedRect {
+        position: Vec2,
+        size: Vec2,
+        radius: f32,
+        color: Color,
+    },
+    Line {
+        start: Vec2,
+        end: Vec2,
+        thickness: f32,
+        color: Color,
+    },
+    Circle {
+        center: Vec2,
+        radius: f32,
+        color: Color,
+    },
+    /// Clip everything drawn after it to a rectangle, within the current clip
+    PushClip { position: Vec2, size: Vec2 },
+    /// Go back to the clip before the last [`PyRenderInstruction::PushClip`]
+    PopClip,
+}
+
+/// Color of everything drawn on a frame: `0xRRGGBB` and an optional alpha from 0 to 1
+fn color(rgb: u32, alpha: OptionalArg<ArgIntoFloat>, vm: &VirtualMachine) -> PyResult<Color> {
+    if rgb > 0xFFFFFF {
+        return Err(vm.new_value_error(format!(
+            "Colors are 0xRRGGBB, got {:#x}. Transparency is a separate alpha argument",
+            rgb
+        )));
+    }
+    let alpha = alpha.map_or(1.0, |alpha| *alpha as f32);
+    if !(0.0..=1.0).contains(&alpha) {
+        return Err(vm.new_value_error(format!("Alpha has to be between 0 and 1, got {}", alpha)));
+    }
+    let rgb = Color::from_hex_rgb(rgb);
+    Ok(Color::from_rgba(rgb.r(), rgb.g(), rgb.b(), alpha))
 }
 
 pub type RenderQueue = Arc<Mutex<Vec<PyRenderInstruction>>>;
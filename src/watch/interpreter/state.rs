@@ -0,0 +1,355 @@
+//! Read-only copy of the game for Python, kept in [`pywatch::Context::state`] and updated every frame
+//! before any Python code runs. Tiles and collision are only copied again when they change
+use super::*;
+use pywatch::PyVec2;
+use vm::builtins::PyDictRef;
+use vm::convert::ToPyObject;
+
+pub struct PlayerState {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub size: Vec2,
+    pub grounded: bool,
+    pub animation: &'static str,
+    pub flip: bool,
+}
+
+pub struct EntityState {
+    pub kind: &'static str,
+    pub position: Vec2,
+    pub top_left: Vec2,
+    pub size: Vec2,
+    /// Condition of a platform
+    pub condition: Option<String>,
+}
+
+pub struct DoorState {
+    pub id: usize,
+    pub bottom: IVec2,
+    pub center: Vec2,
+    pub locked: bool,
+    /// From the center of the player
+    pub distance: f32,
+    /// Whether it can be locked and unlocked, see [`tiles::in_reach`]
+    pub reachable: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct TileState {
+    /// Position of the tile in the tileset
+    pub tile: UVec2,
+    pub solid: bool,
+    pub door: bool,
+}
+
+pub struct State {
+    pub player: PlayerState,
+    pub level_index: usize,
+    pub identifier: String,
+    pub pixel_size: Vec2,
+    pub entities: Vec<EntityState>,
+    /// Size of the layers, in tiles
+    pub size: UVec2,
+    pub solid: Vec<world::SolidTile>,
+    pub foreground: Vec<Option<TileState>>,
+    pub background: Vec<Option<TileState>>,
+    pub collision: collision::CollisionGrid,
+    /// Doors of the level, nearest first
+    pub doors: Vec<DoorState>,
+    pub door_radius: f32,
+    /// Size of a foreground tile, in pixels
+    pub grid_size: Vec2,
+}
+
+impl EntityState {
+    /// Every entity of the level. Taken every frame, as entities can be added and removed
+    fn collect(level: &world::Level) -> Vec<Self> {
+        level
+            .entities
+            .entities()
+            .iter()
+            .map(|entity| {
+                let (kind, condition) = match &entity.entity {
+                    world::Entity::Platform(platform) => {
+                        ("platform", Some(platform.condition.clone()))
+                    }
+                    world::Entity::PlayerStartPosition(_) => ("player_start_position", None),
+                    world::Entity::EndOfTheLevel(_) => ("end_of_the_level", None),
+                    world::Entity::Void(_) => ("void", None),
+                };
+                Self {
+                    kind,
+                    position: entity.position,
+                    top_left: entity.top_left(),
+                    size: entity.size.into_f32(),
+                    condition,
+                }
+            })
+            .collect()
+    }
+
+    fn to_dict(&self, vm: &vm::VirtualMachine) -> vm::PyResult<PyDictRef> {
+        let dict = vm.ctx.new_dict();
+        dict.set_item("type", self.kind.to_pyobject(vm), vm)?;
+        dict.set_item("position", PyVec2(self.position).to_pyobject(vm), vm)?;
+        dict.set_item("size", PyVec2(self.size).to_pyobject(vm), vm)?;
+        if let Some(condition) = &self.condition {
+            dict.set_item("condition", condition.clone().to_pyobject(vm), vm)?;
+        }
+        Ok(dict)
+    }
+}
+
+fn cell_kind(cell: collision::Cell) -> &'static str {
+    match cell {
+        collision::Cell::Empty => "empty",
+        collision::Cell::Solid => "solid",
+        collision::Cell::Slippery => "slippery",
+    }
+}
+
+impl PlayerState {
+    fn new(player: &Player) -> Self {
+        Self {
+            position: player.position,
+            velocity: player.velocity,
+            size: player.size.into_f32(),
+            grounded: player.grounded,
+            animation: player.animation,
+            flip: player.flip,
+        }
+    }
+}
+
+impl State {
+    pub fn new(
+        level_index: usize,
+        identifier: &str,
+        rules: &rules::LevelRules,
+        level: &world::Level,
+        tiles: &tiles::TileTable,
+        player: &Player,
+    ) -> Self {
+        let tile_state = |tile: &Option<world::Tile>| {
+            tile.as_ref().map(|tile| {
+                let properties = tiles.get(tile.position);
+                TileState {
+                    tile: tile.position,
+                    solid: properties.solid,
+                    door: properties.door,
+                }
+            })
+        };
+        let doors = tiles
+            .doors(level)
+            .into_iter()
+            .map(|door| DoorState {
+                id: door.id,
+                bottom: door.bottom,
+                center: door.center,
+                locked: door.locked,
+                distance: 0.0,
+                reachable: false,
+            })
+            .collect();
+
+        let mut state = Self {
+            player: PlayerState::new(player),
+            level_index,
+            identifier: identifier.to_owned(),
+            pixel_size: level.pixel_size.into_f32(),
+            entities: EntityState::collect(level),
+            size: level.solid.size,
+            solid: level.solid.tiles.clone(),
+            foreground: level.foreground.tiles.iter().map(tile_state).collect(),
+            background: level.background.tiles.iter().map(tile_state).collect(),
+            collision: collision::CollisionGrid::new(level, tiles),
+            doors,
+            door_radius: rules.door_radius,
+            grid_size: level.foreground.grid_size().into_f32(),
+        };
+        state.sort_doors();
+        state
+    }
+
+    /// Take the player and entities again, for a level whose tiles didn't change
+    pub fn update(&mut self, rules: &rules::LevelRules, level: &world::Level, player: &Player) {
+        self.player = PlayerState::new(player);
+        self.entities = EntityState::collect(level);
+        self.collision.update_platforms(level);
+        self.door_radius = rules.door_radius;
+        self.sort_doors();
+    }
+
+    fn sort_doors(&mut self) {
+        let center = self.player.position + self.player.size / 2.0;
+        for door in &mut self.doors {
+            door.distance = (door.center - center).magnitude();
+            door.reachable = tiles::in_reach(door.bottom, center, self.door_radius, self.grid_size);
+        }
+        self.doors.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    }
+
+    pub fn player(&self, vm: &vm::VirtualMachine) -> vm::PyResult<PyDictRef> {
+        let player = &self.player;
+        let dict = vm.ctx.new_dict();
+        dict.set_item("position", PyVec2(player.position).to_pyobject(vm), vm)?;
+        dict.set_item("velocity", PyVec2(player.velocity).to_pyobject(vm), vm)?;
+        dict.set_item("size", PyVec2(player.size).to_pyobject(vm), vm)?;
+        dict.set_item("grounded", player.grounded.to_pyobject(vm), vm)?;
+        dict.set_item("animation", player.animation.to_pyobject(vm), vm)?;
+        dict.set_item("flip", player.flip.to_pyobject(vm), vm)?;
+        Ok(dict)
+    }
+
+    pub fn level(&self, vm: &vm::VirtualMachine) -> vm::PyResult<PyDictRef> {
+        let entities = self
+            .entities
+            .iter()
+            .map(|entity| Ok(entity.to_dict(vm)?.into()))
+            .collect::<vm::PyResult<_>>()?;
+
+        let dict = vm.ctx.new_dict();
+        dict.set_item("index", self.level_index.to_pyobject(vm), vm)?;
+        dict.set_item("identifier", self.identifier.clone().to_pyobject(vm), vm)?;
+        dict.set_item("pixel_size", PyVec2(self.pixel_size).to_pyobject(vm), vm)?;
+        dict.set_item("size", PyVec2(self.size.into_f32()).to_pyobject(vm), vm)?;
+        dict.set_item("entities", vm.ctx.new_list(entities).into(), vm)?;
+        Ok(dict)
+    }
+
+    /// Every door of the level, nearest first
+    pub fn doors(&self, vm: &vm::VirtualMachine) -> vm::PyResult<vm::builtins::PyListRef> {
+        let doors = self
+            .doors
+            .iter()
+            .map(|door| {
+                let dict = vm.ctx.new_dict();
+                dict.set_item("id", door.id.to_pyobject(vm), vm)?;
+                dict.set_item("position", PyVec2(door.center).to_pyobject(vm), vm)?;
+                dict.set_item("distance", door.distance.to_pyobject(vm), vm)?;
+                dict.set_item("locked", door.locked.to_pyobject(vm), vm)?;
+                dict.set_item("reachable", door.reachable.to_pyobject(vm), vm)?;
+                Ok(dict.into())
+            })
+            .collect::<vm::PyResult<_>>()?;
+        Ok(vm.ctx.new_list(doors))
+    }
+
+    /// Raises if there is no such door or it's too far to be locked or unlocked
+    pub fn reachable_door(&self, id: usize, vm: &vm::VirtualMachine) -> vm::PyResult<()> {
+        match self.doors.iter().find(|door| door.id == id) {
+            Some(door) if door.reachable => Ok(()),
+            Some(_) => Err(vm.new_value_error(format!("Door {} is out of reach", id))),
+            None => Err(vm.new_value_error(format!("No door with id {}", id))),
+        }
+    }
+
+    /// Tile of a layer. None if there is no tile or it's outside of the level
+    pub fn tile(
+        &self,
+        layer: &str,
+        x: i32,
+        y: i32,
+        vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<vm::PyObjectRef> {
+        let index = (x >= 0 && y >= 0 && (x as u32) < self.size.x && (y as u32) < self.size.y)
+            .then(|| x as usize + y as usize * self.size.x as usize);
+        let tile = |tiles: &[Option<TileState>]| -> vm::PyResult<vm::PyObjectRef> {
+            let Some(tile) = index.and_then(|index| tiles.get(index).copied().flatten()) else {
+                return Ok(vm.ctx.none());
+            };
+            let dict = vm.ctx.new_dict();
+            dict.set_item("tile", PyVec2(tile.tile.into_f32()).to_pyobject(vm), vm)?;
+            dict.set_item("solid", tile.solid.to_pyobject(vm), vm)?;
+            dict.set_item("door", tile.door.to_pyobject(vm), vm)?;
+            Ok(dict.into())
+        };
+        match layer {
+            "solid" => Ok(
+                match index.and_then(|index| self.solid.get(index).copied()) {
+                    None | Some(world::SolidTile::Empty) => vm.ctx.none(),
+                    Some(world::SolidTile::Ground) => "ground".to_pyobject(vm),
+                    Some(world::SolidTile::Lamp) => "lamp".to_pyobject(vm),
+                },
+            ),
+            "foreground" => tile(&self.foreground),
+            "background" => tile(&self.background),
+            _ => Err(vm.new_value_error(format!("Invalid layer: {:?}", layer))),
+        }
+    }
+
+    /// First solid thing hit by a ray, None if nothing was hit
+    pub fn raycast(
+        &self,
+        origin: Vec2,
+        direction: Vec2,
+        max_distance: f32,
+        vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<vm::PyObjectRef> {
+        if !max_distance.is_finite() || max_distance < 0.0 {
+            return Err(
+                vm.new_value_error("Max distance has to be finite and non-negative".to_owned())
+            );
+        }
+        if !origin.x.is_finite() || !origin.y.is_finite() {
+            return Err(vm.new_value_error("Origin has to be finite".to_owned()));
+        }
+        let Some(direction) = direction.normalize() else {
+            return Err(vm.new_value_error("Direction can't be zero".to_owned()));
+        };
+        let Some(hit) = self.collision.raycast(origin, direction, max_distance) else {
+            return Ok(vm.ctx.none());
+        };
+        let dict = vm.ctx.new_dict();
+        dict.set_item("position", PyVec2(hit.position).to_pyobject(vm), vm)?;
+        dict.set_item("normal", PyVec2(hit.normal).to_pyobject(vm), vm)?;
+        dict.set_item("distance", hit.distance.to_pyobject(vm), vm)?;
+        let (kind, tile) = match hit.cell {
+            Some((position, cell)) => {
+                (cell_kind(cell), PyVec2(position.into_f32()).to_pyobject(vm))
+            }
+            None => ("platform", vm.ctx.none()),
+        };
+        dict.set_item("kind", kind.to_pyobject(vm), vm)?;
+        dict.set_item("tile", tile, vm)?;
+        Ok(dict.into())
+    }
+
+    /// Solid tiles and entities overlapping a box
+    pub fn overlap_rect(
+        &self,
+        tl: Vec2,
+        size: Vec2,
+        vm: &vm::VirtualMachine,
+    ) -> vm::PyResult<PyDictRef> {
+        let br = tl + size;
+        let tiles = self
+            .collision
+            .overlapping_cells(tl, br)
+            .map(|(position, cell)| {
+                let dict = vm.ctx.new_dict();
+                dict.set_item("tile", PyVec2(position.into_f32()).to_pyobject(vm), vm)?;
+                dict.set_item("kind", cell_kind(cell).to_pyobject(vm), vm)?;
+                Ok(dict.into())
+            })
+            .collect::<vm::PyResult<_>>()?;
+        let entities = self
+            .entities
+            .iter()
+            .filter(|entity| {
+                let entity_br = entity.top_left + entity.size;
+                br.x > entity.top_left.x
+                    && br.y > entity.top_left.y
+                    && tl.x < entity_br.x
+                    && tl.y < entity_br.y
+            })
+            .map(|entity| Ok(entity.to_dict(vm)?.into()))
+            .collect::<vm::PyResult<_>>()?;
+
+        let dict = vm.ctx.new_dict();
+        dict.set_item("tiles", vm.ctx.new_list(tiles).into(), vm)?;
+        dict.set_item("entities", vm.ctx.new_list(entities).into(), vm)?;
+        Ok(dict)
+    }
+}
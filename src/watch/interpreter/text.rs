@@ -0,0 +1,82 @@
+//! Text layout for apps, cached between frames so the same text isn't laid out every frame
+use super::*;
+use speedy2d::font::{FormattedTextBlock, TextAlignment, TextLayout, TextOptions};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+pub static LAYOUTS: LazyLock<Mutex<LayoutCache>> =
+    LazyLock::new(|| Mutex::new(LayoutCache::default()));
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    pub fn parse(align: &str, vm: &vm::VirtualMachine) -> vm::PyResult<Self> {
+        match align {
+            "left" => Ok(Self::Left),
+            "center" => Ok(Self::Center),
+            "right" => Ok(Self::Right),
+            _ => Err(vm.new_value_error(format!("Invalid alignment: {:?}", align))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    text: String,
+    /// Bits of the floats, as they can't be hashed
+    size: u32,
+    wrap_width: Option<u32>,
+    align: Align,
+}
+
+/// Layouts used this frame and the last one. Ones that weren't used for a whole frame are dropped
+#[derive(Default)]
+pub struct LayoutCache {
+    current: HashMap<Key, FormattedTextBlock>,
+    previous: HashMap<Key, FormattedTextBlock>,
+}
+
+impl LayoutCache {
+    pub fn next_frame(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    /// Lay out text with the font of the game. Without a wrap width, alignment has no effect
+    pub fn layout(
+        &mut self,
+        text: &str,
+        size: f32,
+        wrap_width: Option<f32>,
+        align: Align,
+    ) -> FormattedTextBlock {
+        let key = Key {
+            text: text.to_owned(),
+            size: size.to_bits(),
+            wrap_width: wrap_width.map(f32::to_bits),
+            align,
+        };
+        if let Some(block) = self.current.get(&key) {
+            return block.clone();
+        }
+        let block = self.previous.remove(&key).unwrap_or_else(|| {
+            let mut options = TextOptions::new();
+            if let Some(wrap_width) = wrap_width {
+                let alignment = match align {
+                    Align::Left => TextAlignment::Left,
+                    Align::Center => TextAlignment::Center,
+                    Align::Right => TextAlignment::Right,
+                };
+                options = options.with_wrap_to_width(wrap_width, alignment);
+            }
+            assets::FONT.layout_text(text, size, options)
+        });
+        self.current.insert(key, block.clone());
+        block
+    }
+}
@@ -0,0 +1,100 @@
+//! Calls scheduled with `watch.after` and `watch.every`
+use super::*;
+use pywatch::PyTimer;
+use vm::builtins::PyTupleRef;
+use vm::{PyObjectRef, TryFromObject};
+
+struct Due {
+    at: f64,
+    timer: PyTimer,
+    interval: Option<f64>,
+    callable: PyObjectRef,
+}
+
+impl Interpreter {
+    /// Advance game time and call timers that are due, in the order they are due.
+    /// Each timer is called at most once per frame: `every` timers that missed intervals (in a long frame)
+    /// skip them and go off at the next multiple of their interval, so they don't try to catch up.
+    /// Timers that raise are canceled
+    pub fn update_timers(&self, delta_time: f32) {
+        let time = {
+            let mut clock = self.context.clock.lock().unwrap();
+            clock.game += delta_time as f64;
+            clock.game
+        };
+
+        let mut due = self
+            .enter(|vm| {
+                let mut due = Vec::new();
+                for (timer, entry) in &pywatch::timers(vm)? {
+                    let timer = PyTimer::try_from_object(vm, timer)?;
+                    let entry = PyTupleRef::try_from_object(vm, entry)?;
+                    let [at, interval, callable] = entry.as_slice() else {
+                        return Err(vm.new_type_error("Invalid timer".to_owned()));
+                    };
+                    let at = f64::try_from_object(vm, at.clone())?;
+                    if at <= time {
+                        due.push(Due {
+                            at,
+                            timer,
+                            interval: Option::<f64>::try_from_object(vm, interval.clone())?,
+                            callable: callable.clone(),
+                        });
+                    }
+                }
+                Ok(due)
+            })
+            .unwrap_or_default();
+        due.sort_by(|a, b| a.at.total_cmp(&b.at).then(a.timer.cmp(&b.timer)));
+
+        for timer in due {
+            self.enter(|vm| {
+                let timers = pywatch::timers(vm)?;
+                // An earlier timer might have canceled this one
+                if timers.get_item_opt(&timer.timer, vm)?.is_none() {
+                    return Ok(());
+                }
+                if timer.interval.is_none() {
+                    timers.del_item(&timer.timer, vm)?;
+                }
+                if let Err(err) = timer.callable.call((), vm) {
+                    if timers.get_item_opt(&timer.timer, vm)?.is_some() {
+                        timers.del_item(&timer.timer, vm)?;
+                    }
+                    return Err(err);
+                }
+                if let Some(interval) = timer.interval {
+                    if timers.get_item_opt(&timer.timer, vm)?.is_some() {
+                        let missed = ((time - timer.at) / interval).floor();
+                        let at = timer.at + (missed + 1.0) * interval;
+                        let entry = vm.new_tuple((at, interval, timer.callable));
+                        timers.set_item(&timer.timer, entry.into(), vm)?;
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Interpreter;
+
+    #[test]
+    fn every_skips_missed_intervals() {
+        let mut interpreter = Interpreter::default();
+        interpreter.initialize();
+        interpreter
+            .run("calls = []\nwatch.every(0.1, lambda: calls.append(watch.time()))")
+            .unwrap();
+        interpreter.update_timers(0.35);
+        interpreter.update_timers(0.02);
+        interpreter.update_timers(0.04);
+        interpreter.update_timers(0.1);
+        let output = interpreter
+            .run("print([round(call, 2) for call in calls], [round(timer[0], 2) for timer in watch._timers.values()])")
+            .unwrap();
+        assert_eq!(output, "[0.35, 0.41, 0.51] [0.6]");
+    }
+}
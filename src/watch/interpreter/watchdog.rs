@@ -0,0 +1,356 @@
+//! Stops Python code that runs for too long, by raising an exception inside of the VM.
+//! Player's code is compiled with [`compile`], which makes every loop, comprehension and function
+//! call `__budget__()`, so even `while True: pass` gets stopped, on web too.
+//! Natively, a thread also raises it with a signal, which the VM checks between instructions,
+//! so code that is not compiled with [`compile`] (built-in apps, `exec` of a string) is stopped there
+use super::vm;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use vm::compiler::parser::ast::{self, fold, located, source_code::SourceRange, Fold};
+use vm::signal::UserSignalSender;
+
+/// Time a single call into Python (app frame, `watch.run`, platform condition) can take
+pub const BUDGET: Duration = Duration::from_millis(250);
+/// Time `tick` of a background app can take. It runs every frame for every app, so it has to be quick
+pub const BACKGROUND_BUDGET: Duration = Duration::from_millis(5);
+
+/// Builtin compiled code calls to check the budget
+const CHECK: &str = "__budget__";
+
+/// Budget of the call that is running, shared by the [`Watchdog`] and [`super::pywatch::Context`]
+#[derive(Default)]
+pub struct Budget {
+    /// [`super::pywatch::real_time`] the call has to end by, and its budget
+    deadline: Mutex<Option<(f64, Duration)>>,
+    /// Raise `KeyboardInterrupt` in the next check
+    interrupt: AtomicBool,
+}
+
+impl Budget {
+    /// Interrupt the player's code that runs next, until the next call of this
+    pub fn set_interrupt(&self, interrupt: bool) {
+        self.interrupt.store(interrupt, Ordering::Relaxed);
+    }
+
+    /// Whether the code was asked to be interrupted, which it then no longer is
+    pub fn take_interrupt(&self) -> bool {
+        self.interrupt.swap(false, Ordering::Relaxed)
+    }
+
+    /// Raises `KeyboardInterrupt` if asked to, and `TimeoutError` after the deadline.
+    /// It's raised again at every check, so catching it doesn't keep the code running
+    fn check(&self, vm: &vm::VirtualMachine) -> vm::PyResult<()> {
+        if self.take_interrupt() {
+            return Err(vm.new_exception_empty(vm.ctx.exceptions.keyboard_interrupt.to_owned()));
+        }
+        match *self.deadline.lock().unwrap() {
+            Some((deadline, budget)) if super::pywatch::real_time() > deadline => {
+                Err(timeout(vm, budget))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn timeout(vm: &vm::VirtualMachine, budget: Duration) -> vm::builtins::PyBaseExceptionRef {
+    vm.new_exception_msg(
+        vm.ctx.exceptions.timeout_error.to_owned(),
+        format!("Ran for longer than {:?}", budget),
+    )
+}
+
+/// `__budget__()`, returns True so it can be a condition of a comprehension
+pub fn check(vm: &vm::VirtualMachine) -> vm::PyResult<bool> {
+    super::pywatch::context().budget.check(vm)?;
+    Ok(true)
+}
+
+/// Compile player's code, making every loop, comprehension and function check the budget
+pub fn compile(
+    vm: &vm::VirtualMachine,
+    source: &str,
+    mode: vm::compiler::Mode,
+    source_path: String,
+) -> Result<vm::PyRef<vm::builtins::PyCode>, vm::compiler::CompileError> {
+    let mut locator = vm::compiler::LinearLocator::new(source);
+    let ast = vm::compiler::parser::parse(source, mode.into(), &source_path)
+        .map_err(|err| locator.locate_error(err))?;
+    let ast = locator.fold_mod(ast).unwrap_or_else(|err| match err {});
+    let ast = Instrument.fold_mod(ast).unwrap_or_else(|err| match err {});
+    let code =
+        vm::compiler::codegen::compile::compile_top(&ast, source_path, mode, vm.compile_opts())
+            .map_err(|err| err.into())?;
+    Ok(vm.ctx.new_code(code))
+}
+
+/// Adds calls of [`CHECK`] to located code, see [`compile`]
+struct Instrument;
+
+impl Instrument {
+    fn check(range: SourceRange) -> located::Expr {
+        located::Expr::Call(located::ExprCall {
+            range,
+            func: Box::new(located::Expr::Name(located::ExprName {
+                range,
+                id: ast::Identifier::new(CHECK),
+                ctx: ast::ExprContext::Load,
+            })),
+            args: Vec::new(),
+            keywords: Vec::new(),
+        })
+    }
+
+    /// Check at the start of a body, after its docstring
+    fn prepend(body: &mut Vec<located::Stmt>, range: SourceRange) {
+        let docstring = matches!(
+            body.first(),
+            Some(located::Stmt::Expr(located::StmtExpr { value, .. }))
+                if matches!(**value, located::Expr::Constant(located::ExprConstant {
+                    value: ast::Constant::Str(_),
+                    ..
+                }))
+        );
+        let check = located::Stmt::Expr(located::StmtExpr {
+            range,
+            value: Box::new(Self::check(range)),
+        });
+        body.insert(docstring as usize, check);
+    }
+}
+
+impl Fold<SourceRange> for Instrument {
+    type TargetU = SourceRange;
+    type Error = std::convert::Infallible;
+    type UserContext = ();
+
+    fn will_map_user(&mut self, _user: &SourceRange) {}
+
+    fn map_user(&mut self, user: SourceRange, _context: ()) -> Result<SourceRange, Self::Error> {
+        Ok(user)
+    }
+
+    fn fold_stmt_while(
+        &mut self,
+        node: located::StmtWhile,
+    ) -> Result<located::StmtWhile, Self::Error> {
+        let mut node = fold::fold_stmt_while(self, node)?;
+        Self::prepend(&mut node.body, node.range);
+        Ok(node)
+    }
+
+    fn fold_stmt_for(&mut self, node: located::StmtFor) -> Result<located::StmtFor, Self::Error> {
+        let mut node = fold::fold_stmt_for(self, node)?;
+        Self::prepend(&mut node.body, node.range);
+        Ok(node)
+    }
+
+    fn fold_stmt_async_for(
+        &mut self,
+        node: located::StmtAsyncFor,
+    ) -> Result<located::StmtAsyncFor, Self::Error> {
+        let mut node = fold::fold_stmt_async_for(self, node)?;
+        Self::prepend(&mut node.body, node.range);
+        Ok(node)
+    }
+
+    /// Catches recursion that is deep, but within the recursion limit
+    fn fold_stmt_function_def(
+        &mut self,
+        node: located::StmtFunctionDef,
+    ) -> Result<located::StmtFunctionDef, Self::Error> {
+        let mut node = fold::fold_stmt_function_def(self, node)?;
+        Self::prepend(&mut node.body, node.range);
+        Ok(node)
+    }
+
+    fn fold_stmt_async_function_def(
+        &mut self,
+        node: located::StmtAsyncFunctionDef,
+    ) -> Result<located::StmtAsyncFunctionDef, Self::Error> {
+        let mut node = fold::fold_stmt_async_function_def(self, node)?;
+        Self::prepend(&mut node.body, node.range);
+        Ok(node)
+    }
+
+    /// Comprehensions can't have statements, so the check is their first condition
+    fn fold_comprehension(
+        &mut self,
+        node: located::Comprehension,
+    ) -> Result<located::Comprehension, Self::Error> {
+        use located::Located;
+        let mut node = fold::fold_comprehension(self, node)?;
+        node.ifs.insert(0, Self::check(node.iter.range()));
+        Ok(node)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct Deadline {
+    /// Call to time out, when, and its budget
+    deadline: Option<(u64, std::time::Instant, Duration)>,
+    stopped: bool,
+}
+
+pub struct Watchdog {
+    sender: UserSignalSender,
+    budget: Arc<Budget>,
+    /// Call that is running, 0 if none. Signals are delivered late, so they check it's still the same call
+    current: Arc<AtomicU64>,
+    next: AtomicU64,
+    #[cfg(not(target_arch = "wasm32"))]
+    shared: Arc<(Mutex<Deadline>, Condvar)>,
+}
+
+impl Watchdog {
+    pub fn new(sender: UserSignalSender, budget: Arc<Budget>) -> Self {
+        let watchdog = Self {
+            sender,
+            budget,
+            current: Arc::new(AtomicU64::new(0)),
+            next: AtomicU64::new(1),
+            #[cfg(not(target_arch = "wasm32"))]
+            shared: Arc::new((Mutex::new(Deadline::default()), Condvar::new())),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let shared = watchdog.shared.clone();
+            let sender = watchdog.sender.clone();
+            let current = watchdog.current.clone();
+            std::thread::spawn(move || Self::watch(shared, sender, current));
+        }
+        watchdog
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn watch(
+        shared: Arc<(Mutex<Deadline>, Condvar)>,
+        sender: UserSignalSender,
+        current: Arc<AtomicU64>,
+    ) {
+        let (state, condvar) = &*shared;
+        let mut state = state.lock().unwrap();
+        while !state.stopped {
+            let Some((call, deadline, budget)) = state.deadline else {
+                state = condvar.wait(state).unwrap();
+                continue;
+            };
+            let now = std::time::Instant::now();
+            if now < deadline {
+                state = condvar.wait_timeout(state, deadline - now).unwrap().0;
+                continue;
+            }
+            state.deadline = None;
+            let current = current.clone();
+            // Fails only if the VM is gone, then there's nothing to stop
+            let _ = sender.send(Box::new(move |vm| {
+                if current.load(Ordering::Acquire) == call {
+                    Err(timeout(vm, budget))
+                } else {
+                    Ok(())
+                }
+            }));
+        }
+    }
+
+    /// Run a call into Python within [`BUDGET`]
+    pub fn guard<R>(&self, call: impl FnOnce() -> R) -> R {
+        self.guard_within(BUDGET, call)
+    }
+
+    /// Like [`Watchdog::guard`], with a different budget
+    pub fn guard_within<R>(&self, budget: Duration, call: impl FnOnce() -> R) -> R {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let previous = self.current.swap(id, Ordering::AcqRel);
+        let deadline = super::pywatch::real_time() + budget.as_secs_f64();
+        let previous_deadline = self
+            .budget
+            .deadline
+            .lock()
+            .unwrap()
+            .replace((deadline, budget));
+        #[cfg(not(target_arch = "wasm32"))]
+        let previous_native = {
+            let (state, condvar) = &*self.shared;
+            let deadline = (id, std::time::Instant::now() + budget, budget);
+            let previous = state.lock().unwrap().deadline.replace(deadline);
+            condvar.notify_one();
+            previous
+        };
+
+        let result = call();
+
+        // Calls can be nested, then the outer one is guarded again
+        self.current.store(previous, Ordering::Release);
+        *self.budget.deadline.lock().unwrap() = previous_deadline;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (state, condvar) = &*self.shared;
+            state.lock().unwrap().deadline = previous_native;
+            condvar.notify_one();
+        }
+        result
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (state, condvar) = &*self.shared;
+            state.lock().unwrap().stopped = true;
+            condvar.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Interpreter;
+
+    fn run(interpreter: &mut Interpreter, code: &str) -> String {
+        interpreter.run(code).unwrap_err()
+    }
+
+    #[test]
+    fn checks_stop_loops_that_catch_the_timeout() {
+        // The signal is sent once, so only the checks can stop this
+        let mut interpreter = Interpreter::default();
+        let error = run(
+            &mut interpreter,
+            "while True:\n    try:\n        while True: pass\n    except TimeoutError: pass",
+        );
+        assert!(
+            error.ends_with("TimeoutError: Ran for longer than 250ms\n"),
+            "{}",
+            error
+        );
+        let error = run(&mut interpreter, "[0 for _ in iter(int, 1)]");
+        assert!(error.contains("TimeoutError"), "{}", error);
+    }
+
+    #[test]
+    fn interrupts_the_code_that_runs_next() {
+        let mut interpreter = Interpreter::default();
+        interpreter.context.budget.set_interrupt(true);
+        let error = run(
+            &mut interpreter,
+            "def forever():\n    while True: pass\nforever()",
+        );
+        assert!(error.ends_with("KeyboardInterrupt\n"), "{}", error);
+        assert!(!interpreter.context.budget.take_interrupt());
+    }
+
+    #[test]
+    fn keeps_docstrings() {
+        let mut interpreter = Interpreter::default();
+        let output = interpreter
+            .run("def f():\n    'Doc'\nprint(f.__doc__)")
+            .unwrap();
+        assert_eq!(output, "Doc");
+    }
+}
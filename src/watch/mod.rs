@@ -5,10 +5,16 @@ pub mod interpreter;
 pub use interpreter::Interpreter;
 
 const APP_SIZE: u32 = 24;
+/// Size of the watch screen, in world pixels
+pub const SCREEN_SIZE: Vec2 = Vec2::new(128.0, 128.0);
 
 pub struct Watch {
     pub open: bool,
     pub apps: Vec<App>,
+    /// Module of the app that is open
+    pub open_app: Option<&'static str>,
+    /// Shown instead of the app that crashed, until reopened or dismissed
+    pub crash: Option<Crash>,
 
     pub interpreter: Interpreter,
 }
@@ -17,112 +23,278 @@ impl Default for Watch {
     fn default() -> Self {
         Self {
             open: false,
-            apps: vec![
-                App::new(UVec2::new(0, 0), "weather"),
-                App::new(UVec2::new(1, 0), "keyring"),
-                App::new(UVec2::new(2, 0), "placeholder"),
-                App::new(UVec2::new(3, 0), "terminal"),
-            ],
+            apps: Self::default_apps(),
+            open_app: None,
+            crash: None,
             interpreter: Interpreter::default(),
         }
     }
 }
 
 impl Watch {
+    /// Built-in apps
+    pub fn default_apps() -> Vec<App> {
+        vec![
+            App::new(UVec2::new(0, 0), "weather"),
+            App::new(UVec2::new(1, 0), "keyring"),
+            App::new(UVec2::new(2, 0), "placeholder"),
+            App::new(UVec2::new(3, 0), "terminal"),
+        ]
+    }
+
+    /// Rectangle of the watch screen, in world pixels
+    pub fn screen_space(screen_size: Vec2) -> Rect {
+        let center = screen_size / 2.0;
+        Rect::new(center - SCREEN_SIZE / 2.0, center + SCREEN_SIZE / 2.0)
+    }
+
+    /// Position of the app icon on the home screen
+    pub fn app_position(screen_space: &Rect, index: usize) -> Vec2 {
+        let padding = 4.0; // (screen_space.width() - APP_SIZE as f32 * 4.0) / 3.0;
+        let columns = ((screen_space.width() - APP_SIZE as f32) / (APP_SIZE as f32 + padding))
+            .floor() as usize
+            + 1;
+        *screen_space.top_left()
+            + Vec2::new(
+                (index % columns) as f32 * (APP_SIZE as f32 + padding),
+                (index / columns) as f32 * (APP_SIZE as f32 + padding),
+            )
+    }
+
+    /// Returns true if tiles of the level were changed
     #[allow(clippy::too_many_arguments)]
-    pub fn draw(
+    pub fn update(
         &mut self,
-        helper: &mut WindowHelper,
         delta_time: f32,
         controls: &Controls,
-        camera: &mut Camera,
-        assets: &Assets,
+        scale: f32,
+        screen_size: Vec2,
+        level_index: usize,
+        identifier: &str,
+        rules: &rules::LevelRules,
         level: &mut world::Level,
+        tiles: &tiles::TileTable,
         player: &Player,
-    ) {
+    ) -> bool {
+        let mut tiles_changed = false;
         {
-            let weather = *interpreter::pywatch::WEATHER.lock().unwrap();
-            let (from, to) = if weather == interpreter::pywatch::Weather::Snowy {
-                (UVec2::new(7, 0), UVec2::new(7, 1))
-            } else {
-                (UVec2::new(7, 1), UVec2::new(7, 0))
-            };
+            let snowy = self.interpreter.weather == interpreter::pywatch::Weather::Snowy;
             for tile in level.background.tiles.iter_mut().flatten() {
-                if tile.position == from {
+                let properties = tiles.get(tile.position);
+                let to = if snowy {
+                    properties.frozen
+                } else {
+                    properties.thawed
+                };
+                if let Some(to) = to {
                     tile.position = to;
+                    tiles_changed = true;
                 }
             }
         }
 
+        self.interpreter.state_outdated |= tiles_changed;
+        // Python blocks the game while it runs, so Ctrl+C stops the code that runs in the next frame
         self.interpreter
-            .update(delta_time, camera.graphics, level, player, &mut self.apps);
+            .context
+            .budget
+            .set_interrupt(controls.interrupt());
+        tiles_changed |= self.interpreter.update(
+            delta_time,
+            level_index,
+            identifier,
+            rules,
+            level,
+            tiles,
+            player,
+            &mut self.apps,
+        );
         if controls.watch_toggle() {
             self.open = !self.open;
         }
         if !self.open {
-            return;
+            return tiles_changed;
         }
 
-        let center = helper.get_size_pixels().into_f32() / 2.0;
-        let size = assets.watch.image.size().into_f32() * camera.scale;
-        camera.graphics.draw_rectangle_image(
-            Rect::new(center - size / 2.0, center + size / 2.0),
-            &assets.watch.image,
-        );
-
-        let center = center / camera.scale;
-        let size = Vec2::new(128.0, 128.0);
-        let screen_space = Rect::new(center - size / 2.0, center + size / 2.0);
-        let mouse_pos = controls.mouse_pos / camera.scale;
+        let screen_space = Self::screen_space(screen_size);
+        let mouse_pos = controls.mouse_pos / scale;
 
-        if self.interpreter.current_app.is_some() {
+        if let Some(crash) = &self.crash {
+            let [reopen, dismiss] = Crash::buttons(&screen_space);
+            if controls.click() && reopen.contains(mouse_pos) {
+                let module = crash.module;
+                self.crash = None;
+                self.open(module);
+            } else if controls.click() && dismiss.contains(mouse_pos) {
+                self.crash = None;
+            }
+        } else if self.interpreter.current_app.is_some() {
             let mouse_pos = mouse_pos - screen_space.top_left();
             let frame = interpreter::pywatch::Frame {
                 controls: controls.clone(),
                 mouse_pos,
                 render_queue: self.interpreter.renderer.render_queue.clone(),
             };
-            self.interpreter.frame(camera, assets, screen_space, frame);
+            if let Err(message) = self.interpreter.frame(frame) {
+                self.crash = self.open_app.map(|module| Crash { module, message });
+            }
         } else {
-            let mut cursor = *screen_space.top_left();
-            let padding = 4.0; // (screen_space.width() - APP_SIZE as f32 * 4.0) / 3.0;
-            for app in &self.apps {
-                if Rect::new(cursor, cursor + Vec2::new(APP_SIZE as f32, APP_SIZE as f32))
+            let clicked = self.apps.iter().enumerate().find(|(index, _)| {
+                let cursor = Self::app_position(&screen_space, *index);
+                Rect::new(cursor, cursor + Vec2::new(APP_SIZE as f32, APP_SIZE as f32))
                     .contains(mouse_pos)
                     && controls.click()
-                {
-                    self.interpreter.current_app = self.interpreter.enter(|vm| {
-                        if let Ok(module) = self
-                            .interpreter
-                            .player_scope
-                            .globals
-                            .get_item(app.module, vm)
-                        {
-                            Ok(module)
-                        } else {
-                            vm.import(app.module, 0)
-                        }
-                    });
-                }
-                app.draw(cursor, camera, assets);
-                cursor.x += APP_SIZE as f32 + padding;
-                if cursor.x + APP_SIZE as f32 > screen_space.right() {
-                    cursor.x = screen_space.left();
-                    cursor.y += APP_SIZE as f32 + padding;
-                }
+            });
+            if let Some((_, app)) = clicked {
+                self.open(app.module);
+            }
+        }
+        tiles_changed
+    }
+
+    /// Open an app, showing the crash screen if importing it fails
+    pub fn open(&mut self, module: &'static str) {
+        self.open_app = Some(module);
+        match self.interpreter.try_enter(false, |vm| vm.import(module, 0)) {
+            Ok(app) => self.interpreter.current_app = Some(app),
+            Err(message) => {
+                log_error(message.trim_end());
+                self.crash = Some(Crash { module, message });
+            }
+        }
+    }
+
+    pub fn draw(&mut self, camera: &mut Camera, assets: &Assets, screen_size: Vec2) {
+        self.interpreter
+            .renderer
+            .upload_images(camera.graphics, &self.interpreter.context);
+        if !self.open {
+            return;
+        }
+
+        let center = screen_size / 2.0 * camera.scale;
+        let size = assets.watch.image.size().into_f32() * camera.scale;
+        camera.graphics.draw_rectangle_image(
+            Rect::new(center - size / 2.0, center + size / 2.0),
+            &assets.watch.image,
+        );
+
+        let screen_space = Self::screen_space(screen_size);
+        if let Some(crash) = &self.crash {
+            crash.draw(camera, assets, screen_space);
+        } else if self.interpreter.current_app.is_some() {
+            self.interpreter.renderer.frame(camera, screen_space);
+        } else {
+            for (index, app) in self.apps.iter().enumerate() {
+                app.draw(Self::app_position(&screen_space, index), camera, assets);
             }
         }
     }
 }
 
+/// An app that raised an exception
+pub struct Crash {
+    pub module: &'static str,
+    /// Formatted exception, with the traceback
+    pub message: String,
+}
+
+impl Crash {
+    const BUTTON_SIZE: Vec2 = Vec2::new(56.0, 12.0);
+
+    /// Reopen and dismiss buttons
+    pub fn buttons(screen_space: &Rect) -> [Rect; 2] {
+        let top = screen_space.bottom_right().y - Self::BUTTON_SIZE.y - 2.0;
+        [
+            screen_space.top_left().x + 4.0,
+            screen_space.bottom_right().x - Self::BUTTON_SIZE.x - 4.0,
+        ]
+        .map(|left| {
+            Rect::new(
+                Vec2::new(left, top),
+                Vec2::new(left, top) + Self::BUTTON_SIZE,
+            )
+        })
+    }
+
+    pub fn draw(&self, camera: &mut Camera, assets: &Assets, screen_space: Rect) {
+        let scale = camera.scale;
+        let layout = |text: &str, size: f32, width: f32| {
+            assets.font.layout_text(
+                text,
+                size * scale,
+                speedy2d::font::TextOptions::new()
+                    .with_wrap_to_width(width * scale, speedy2d::font::TextAlignment::Left),
+            )
+        };
+        let top_left = *screen_space.top_left() + Vec2::new(2.0, 2.0);
+        let width = screen_space.width() - 4.0;
+
+        let title = layout(&format!("{} crashed", self.module), 10.0, width);
+        camera
+            .graphics
+            .draw_text(top_left * scale, Color::from_hex_rgb(0xff5555), &title);
+
+        // The end of the traceback has the exception, so if it doesn't fit, the start is cut off
+        let body = Rect::new(
+            top_left + Vec2::new(0.0, title.height() / scale + 2.0),
+            Vec2::new(
+                top_left.x + width,
+                Self::buttons(&screen_space)[0].top_left().y - 2.0,
+            ),
+        );
+        let traceback = layout(self.message.trim_end(), 6.0, width);
+        let offset = (traceback.height() - body.height() * scale).max(0.0);
+        camera
+            .graphics
+            .set_clip(Some(speedy2d::shape::Rectangle::new(
+                (*body.top_left() * scale).into_i32(),
+                (*body.bottom_right() * scale).into_i32(),
+            )));
+        camera.graphics.draw_text(
+            *body.top_left() * scale - Vec2::new(0.0, offset),
+            Color::WHITE,
+            &traceback,
+        );
+        camera.graphics.set_clip(None);
+
+        use speedy2d::numeric::RoundFloat;
+        for (button, label) in Self::buttons(&screen_space)
+            .iter()
+            .zip(["Reopen", "Dismiss"])
+        {
+            camera.graphics.draw_rectangle(
+                Rect::new(*button.top_left() * scale, *button.bottom_right() * scale),
+                Color::from_hex_rgb(0x686f99),
+            );
+            let text = layout(label, 8.0, Self::BUTTON_SIZE.x);
+            let position =
+                (*button.top_left() + Self::BUTTON_SIZE / 2.0) * scale - text.size() / 2.0;
+            camera
+                .graphics
+                .draw_text(position.round(), Color::WHITE, &text);
+        }
+    }
+}
+
 pub struct App {
-    icon: UVec2,
-    module: &'static str,
+    pub icon: UVec2,
+    /// Module of the app, which is its namespace
+    pub module: &'static str,
+    /// Code of the module, if it was added with it
+    pub source: Option<String>,
+    /// Whether `tick` of the app is called every frame. The user can turn it off
+    pub background: bool,
 }
 
 impl App {
     pub fn new(icon: UVec2, module: &'static str) -> Self {
-        Self { icon, module }
+        Self {
+            icon,
+            module,
+            source: None,
+            background: true,
+        }
     }
 
     pub fn draw(&self, position: Vec2, camera: &mut Camera, assets: &Assets) {
@@ -553,7 +553,7 @@ pub enum SolidTile {
 pub struct Solid {
     pub size: UVec2,
     pub tiles: Vec<SolidTile>,
-    auto_tiles: Vec<Vec<Tile>>,
+    pub auto_tiles: Vec<Vec<Tile>>,
 }
 
 impl traits::Layer for Solid {